@@ -0,0 +1,61 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use sjprofiler::{c_str, code_cache::{CodeCache, CodeCacheArray}};
+
+const LIBS: usize = 500;
+const LIB_SIZE: usize = 0x10000;
+const LIB_BASE: usize = 0x7f00_0000_0000;
+
+/// the process with hundreds of mappings, every library has a few symbols.
+fn code_caches() -> CodeCacheArray {
+    let array = CodeCacheArray::new(2048);
+    for i in 0..LIBS {
+        let start = LIB_BASE + i * 2 * LIB_SIZE;
+        let mut cc = unsafe {
+            CodeCache::new_with_address_range(c_str!("lib"), i as _, start as _, (start + LIB_SIZE) as _)
+        };
+        for f in 0..16 {
+            unsafe { cc.add((start + f * 0x1000) as _, 0x1000, c_str!("func"), false) };
+        }
+        cc.sort();
+        array.add(cc);
+    }
+    array.update_index();
+    array
+}
+
+fn addresses() -> Vec<usize> {
+    // the simple LCG, keep the addresses stable between the runs.
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    (0..1024)
+        .map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            LIB_BASE + (seed >> 33) as usize % (LIBS * 2 * LIB_SIZE)
+        })
+        .collect()
+}
+
+fn bench_find_library(c: &mut Criterion) {
+    let array = code_caches();
+    let addrs = addresses();
+    let mut group = c.benchmark_group("find_library");
+    group.bench_function("linear", |b| {
+        b.iter(|| {
+            addrs
+                .iter()
+                .filter(|addr| array.find_linear(black_box(**addr as _)).is_some())
+                .count()
+        })
+    });
+    group.bench_function("index", |b| {
+        b.iter(|| {
+            addrs
+                .iter()
+                .filter(|addr| array.find(black_box(**addr as _)).is_some())
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_library);
+criterion_main!(benches);
@@ -0,0 +1,132 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+use crate::{
+    frame_type::FrameType,
+    jvmti_native::jmethodID,
+    method_dict::MethodDict,
+    vm::JVMPICallFrame,
+};
+
+/// the samples of the method or the line.
+#[derive(Default)]
+struct Samples {
+    samples: u64,
+    /// the samples by the bci.
+    bcis: HashMap<i32, u64>,
+}
+
+/// Break down the samples of the top java frame by the bci, and by the line at the report.
+pub struct BciHistogram {
+    methods: HashMap<jmethodID, Samples>,
+    total: u64,
+}
+
+impl BciHistogram {
+    pub fn new() -> Self {
+        Self {
+            methods: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    /// the frames are callee first, the native frames above the java frames are skipped.
+    pub fn add(&mut self, frames: &[JVMPICallFrame], samples: u64) {
+        self.total += samples;
+        if let Some(frame) = frames.iter().find(|f| f.is_java()) {
+            let (_, bci) = FrameType::decode(frame.bci);
+            let method = self.methods.entry(frame.method_id).or_default();
+            method.samples += samples;
+            *method.bcis.entry(bci).or_default() += samples;
+        }
+    }
+
+    /// the hottest methods, most samples first.
+    pub fn top(&self, n: usize) -> Vec<jmethodID> {
+        let mut methods: Vec<_> = self.methods.iter().collect();
+        methods.sort_by_key(|(_, m)| Reverse(m.samples));
+        methods.into_iter().take(n).map(|(id, _)| *id).collect()
+    }
+
+    /// annotate the top methods by the line, the methods must be interned in the dict.
+    pub fn report(&self, n: usize, dict: &MethodDict, out: &mut String) {
+        let percent = |n: u64, total: u64| if total == 0 { 0f64 } else { n as f64 * 100f64 / total as f64 };
+        for id in self.top(n) {
+            let method = &self.methods[&id];
+            let entry = dict.lookup(id).and_then(|idx| dict.get(idx));
+            let _ = match entry {
+                Some(e) => write!(out, "{}.{}{}", e.class, e.name, e.sig),
+                None => write!(out, "[unknown]"),
+            };
+            let source_file = entry.and_then(|e| e.source_file.as_deref()).unwrap_or("Unknown");
+            let _ = writeln!(
+                out, " ({source_file}) samples: {} ({:.2}%)",
+                method.samples, percent(method.samples, self.total)
+            );
+            let mut lines: BTreeMap<Option<i32>, Samples> = BTreeMap::new();
+            for (bci, samples) in method.bcis.iter() {
+                let line = lines.entry(entry.and_then(|e| e.line_of(*bci))).or_default();
+                line.samples += samples;
+                line.bcis.insert(*bci, *samples);
+            }
+            let _ = writeln!(out, "  {:>6} {:>10} {:>8}  bci:samples", "line", "samples", "percent");
+            for (line, samples) in lines {
+                let line = line.map_or("?".to_string(), |l| l.to_string());
+                let _ = write!(
+                    out, "  {line:>6} {:>10} {:>7.2}% ",
+                    samples.samples, percent(samples.samples, method.samples)
+                );
+                let mut bcis: Vec<_> = samples.bcis.into_iter().collect();
+                bcis.sort();
+                for (bci, samples) in bcis {
+                    let _ = write!(out, " {bci}:{samples}");
+                }
+                out.push('\n');
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::method_dict::MethodEntry;
+    use crate::vm::{BCI_NATIVE_FRAME, BCI_THREADID};
+
+    #[test]
+    fn test_report() {
+        let (hot, cold) = (0x10 as jmethodID, 0x20 as jmethodID);
+        let mut dict = MethodDict::new();
+        dict.insert(hot, MethodEntry {
+            class: "Foo".into(),
+            name: "loop".into(),
+            sig: "()V".into(),
+            source_file: Some("Foo.java".into()),
+            line_table: vec![(0, 10), (5, 11), (20, 14)],
+        });
+        let frame = |method_id, bci| JVMPICallFrame { bci, method_id };
+        let mut histogram = BciHistogram::new();
+        let trace = |bci| vec![
+            frame(0x1 as _, BCI_NATIVE_FRAME),
+            frame(hot, FrameType::Compiled(4).encode(bci)),
+            frame(cold, 3),
+            frame(0x1 as _, BCI_THREADID),
+        ];
+        histogram.add(&trace(6), 30);
+        histogram.add(&trace(8), 50);
+        histogram.add(&trace(22), 20);
+        histogram.add(&[frame(cold, 1)], 10);
+        assert_eq!(histogram.top(1), vec![hot]);
+
+        let mut out = String::new();
+        histogram.report(2, &dict, &mut out);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "Foo.loop()V (Foo.java) samples: 100 (90.91%)");
+        assert!(lines[2].split_whitespace().eq(["11", "80", "80.00%", "6:30", "8:50"]));
+        assert!(lines[3].split_whitespace().eq(["14", "20", "20.00%", "22:20"]));
+        assert_eq!(lines[4], "[unknown] (Unknown) samples: 10 (9.09%)");
+    }
+}
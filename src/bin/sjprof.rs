@@ -0,0 +1,61 @@
+use std::{fs, process};
+
+use sjprofiler::profile_diff::{Profile, ProfileDiff};
+
+const USAGE: &str = "usage: sjprof diff <before> <after> [--top <n>] [--svg <file>] [--folded <file>]
+  compare the collapsed or speedscope profiles dumped by the agent, normalized by the total
+  samples. the frame types are ignored, the method compiled at another tier is the same frame.
+  --top     the regressions and improvements in the table, 20 by default
+  --svg     write the differential flame graph, red for the grown and blue for the shrunk
+  --folded  write the two columns collapsed stacks for the flamegraph.pl";
+
+fn load(path: &str) -> Result<Profile, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+    Profile::parse(&text).map_err(|e| format!("{path}: {e}"))
+}
+
+fn diff(args: &[String]) -> Result<(), String> {
+    let (mut files, mut top, mut svg, mut folded) = (Vec::new(), 20, None, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--top" => top = value()?.parse().map_err(|_| "--top must be a number".to_string())?,
+            "--svg" => svg = Some(value()?),
+            "--folded" => folded = Some(value()?),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+            _ => files.push(arg),
+        }
+    }
+    let (before, after) = match files[..] {
+        [before, after] => (before, after),
+        _ => return Err(USAGE.to_string()),
+    };
+    let diff = ProfileDiff::new(&load(before)?, &load(after)?);
+    if let Some(path) = svg {
+        let mut out = String::new();
+        diff.write_svg(&format!("{before} -> {after}"), &mut out);
+        fs::write(path, out).map_err(|e| format!("{path}: {e}"))?;
+    }
+    if let Some(path) = folded {
+        let mut out = String::new();
+        diff.write_folded(&mut out);
+        fs::write(path, out).map_err(|e| format!("{path}: {e}"))?;
+    }
+    let mut out = String::new();
+    diff.report(top, &mut out);
+    print!("{out}");
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(|s| s.as_str()) {
+        Some("diff") => diff(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        process::exit(1);
+    }
+}
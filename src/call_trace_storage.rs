@@ -0,0 +1,273 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use crate::{
+    c_str,
+    os::OS,
+    vm::{JVMPICallFrame, JVMPICallTrace, BCI_CODE_BLOB},
+};
+
+/// keep the recent samples with the time, the older are only aggregated in the traces.
+const MAX_SAMPLES: usize = 1 << 22;
+/// the distinct traces kept, e.g. the native pcs of the line detail make a trace for
+/// every pc. the new traces after are counted in the overflow trace of their thread.
+const MAX_TRACES: usize = 1 << 16;
+
+/// the sample with the time and the thread, the trace is the index of the traces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// the monotonic nano time taken by the signal handler.
+    pub time: u64,
+    pub tid: u32,
+    pub trace: u32,
+}
+
+/// the time range in nanos since the recording start, the bounds are inclusive.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl TimeRange {
+    /// parse the "from=<ms> to=<ms>" args, both are optional and in millis since the start.
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let mut range = Self::default();
+        for arg in args.split_whitespace() {
+            let (key, value) = arg.split_once('=').ok_or_else(|| format!("bad range arg: {arg}"))?;
+            let millis: f64 = value.parse().map_err(|_| format!("{key} must be the millis: {value}"))?;
+            if millis < 0f64 {
+                return Err(format!("{key} must not be negative: {value}"));
+            }
+            let nanos = Some((millis * 1e6) as u64);
+            match key {
+                "from" => range.from = nanos,
+                "to" => range.to = nanos,
+                _ => return Err(format!("unknown range arg: {key}")),
+            }
+        }
+        match (range.from, range.to) {
+            (Some(from), Some(to)) if from > to => Err("from is after to".to_string()),
+            _ => Ok(range),
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_all(&self) -> bool {
+        self.from.is_none() && self.to.is_none()
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, elapsed: u64) -> bool {
+        self.from.is_none_or(|from| elapsed >= from) && self.to.is_none_or(|to| elapsed <= to)
+    }
+}
+
+/// aggregate the call traces drained from the circle queue.
+/// the frames are callee first, same as the AsyncGetCallTrace.
+/// every sample is also kept with its time and thread, so the traces can be sliced by the time.
+pub struct CallTraceStorage {
+    ids: HashMap<Arc<[JVMPICallFrame]>, u32>,
+    /// (frames, samples) by the trace index.
+    traces: Vec<(Arc<[JVMPICallFrame]>, u64)>,
+    samples: VecDeque<Sample>,
+    /// the nano time of the recording start.
+    start_time: u64,
+    dropped_samples: u64,
+}
+
+impl CallTraceStorage {
+    pub fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            traces: Vec::new(),
+            samples: VecDeque::new(),
+            start_time: OS::nano_time(),
+            dropped_samples: 0,
+        }
+    }
+
+    pub fn add(&mut self, trace: &JVMPICallTrace, time: u64, tid: u32) {
+        if trace.num_frames <= 0 || trace.frames.is_null() {
+            return;
+        }
+        let frames = unsafe { std::slice::from_raw_parts(trace.frames, trace.num_frames as _) };
+        let trace = self.add_frames(frames, 1);
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.pop_front();
+            self.dropped_samples += 1;
+        }
+        self.samples.push_back(Sample { time, tid, trace });
+    }
+
+    /// add the samples without the time, return the trace index.
+    pub fn add_frames(&mut self, frames: &[JVMPICallFrame], samples: u64) -> u32 {
+        if let Some(idx) = self.ids.get(frames) {
+            self.traces[*idx as usize].1 += samples;
+            return *idx;
+        }
+        if self.traces.len() >= MAX_TRACES {
+            return self.add_overflow(frames, samples);
+        }
+        self.insert(frames, samples)
+    }
+
+    /// the overflow trace keeps the outermost frame, the thread frame if the threads are split.
+    fn add_overflow(&mut self, frames: &[JVMPICallFrame], samples: u64) -> u32 {
+        let overflow = [
+            JVMPICallFrame {
+                bci: BCI_CODE_BLOB,
+                method_id: c_str!("[trace_overflow]") as _,
+            },
+            frames.last().copied().unwrap_or_default(),
+        ];
+        match self.ids.get(&overflow[..]) {
+            Some(idx) => {
+                self.traces[*idx as usize].1 += samples;
+                *idx
+            }
+            None => self.insert(&overflow, samples),
+        }
+    }
+
+    fn insert(&mut self, frames: &[JVMPICallFrame], samples: u64) -> u32 {
+        let idx = self.traces.len() as u32;
+        let frames: Arc<[JVMPICallFrame]> = frames.into();
+        self.ids.insert(frames.clone(), idx);
+        self.traces.push((frames, samples));
+        idx
+    }
+
+    #[inline(always)]
+    pub fn start_time(&self) -> u64 {
+        self.start_time
+    }
+
+    /// the samples evicted from the recent samples, they are missed in the sliced traces.
+    #[inline(always)]
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped_samples
+    }
+
+    /// the recent samples in the drained order, roughly ordered by the time.
+    #[inline(always)]
+    pub fn samples(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter()
+    }
+
+    #[inline(always)]
+    pub fn frames(&self, trace: u32) -> &[JVMPICallFrame] {
+        &self.traces[trace as usize].0
+    }
+
+    /// the traces with the samples in the range, all the traces if the range is unbounded.
+    pub fn traces_in(&self, range: TimeRange) -> Vec<(&[JVMPICallFrame], u64)> {
+        if range.is_all() {
+            return self.traces().collect();
+        }
+        let mut counts: HashMap<u32, u64> = HashMap::new();
+        for sample in self.samples.iter() {
+            if range.contains(sample.time.saturating_sub(self.start_time)) {
+                *counts.entry(sample.trace).or_default() += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .map(|(trace, samples)| (self.frames(trace), samples))
+            .collect()
+    }
+
+    #[inline(always)]
+    pub fn traces(&self) -> impl Iterator<Item = (&[JVMPICallFrame], u64)> {
+        self.traces.iter().map(|(frames, n)| (&frames[..], *n))
+    }
+
+    /// clear the traces and restart the recording.
+    pub fn clear(&mut self) {
+        self.ids.clear();
+        self.traces.clear();
+        self.samples.clear();
+        self.start_time = OS::nano_time();
+        self.dropped_samples = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_add_frames() {
+        let mut storage = CallTraceStorage::new();
+        let mut frames = vec![JVMPICallFrame::default(); 3];
+        frames[1].bci = 1;
+        storage.add_frames(&frames, 1);
+        storage.add_frames(&frames, 2);
+        frames[2].bci = 2;
+        let trace = JVMPICallTrace {
+            env: std::ptr::null_mut(),
+            num_frames: frames.len() as _,
+            frames: frames.as_mut_ptr(),
+        };
+        storage.add(&trace, storage.start_time(), 1);
+        assert_eq!(storage.traces().map(|(_, n)| n).sum::<u64>(), 4);
+        assert_eq!(storage.traces().count(), 2);
+        assert!(storage.traces().any(|(_, n)| n == 3));
+        storage.clear();
+        assert_eq!(storage.traces().count(), 0);
+    }
+
+    #[test]
+    fn test_max_traces() {
+        let mut storage = CallTraceStorage::new();
+        let mut frames = vec![JVMPICallFrame::default(); 2];
+        for bci in 0..MAX_TRACES + 10 {
+            frames[0].bci = bci as _;
+            storage.add_frames(&frames, 1);
+        }
+        // the known trace is still counted.
+        frames[0].bci = 0;
+        storage.add_frames(&frames, 1);
+        assert_eq!(storage.traces().count(), MAX_TRACES + 1);
+        let (overflow, samples) = storage.traces().last().unwrap();
+        assert_eq!(overflow[0].bci, BCI_CODE_BLOB);
+        assert!(overflow[1] == frames[1]);
+        assert_eq!(samples, 10);
+        assert_eq!(storage.traces().map(|(_, n)| n).sum::<u64>(), MAX_TRACES as u64 + 11);
+    }
+
+    #[test]
+    fn test_traces_in() {
+        let mut storage = CallTraceStorage::new();
+        let start = storage.start_time();
+        let mut frames = vec![JVMPICallFrame::default(); 2];
+        let mut add = |storage: &mut CallTraceStorage, bci, millis: u64| {
+            frames[0].bci = bci;
+            let trace = JVMPICallTrace {
+                env: std::ptr::null_mut(),
+                num_frames: frames.len() as _,
+                frames: frames.as_mut_ptr(),
+            };
+            storage.add(&trace, start + millis * 1_000_000, 7);
+        };
+        add(&mut storage, 1, 10);
+        add(&mut storage, 1, 20);
+        add(&mut storage, 2, 20);
+        add(&mut storage, 2, 30);
+        assert_eq!(storage.samples().next(), Some(&Sample { time: start + 10_000_000, tid: 7, trace: 0 }));
+        assert_eq!(storage.traces_in(TimeRange::default()).len(), 2);
+
+        let range = TimeRange::parse("from=15 to=20").unwrap();
+        let mut traces = storage.traces_in(range);
+        traces.sort_by_key(|(frames, _)| frames[0].bci);
+        assert_eq!(traces.iter().map(|(f, n)| (f[0].bci, *n)).collect::<Vec<_>>(), vec![(1, 1), (2, 1)]);
+        let range = TimeRange::parse("from=25").unwrap();
+        assert_eq!(storage.traces_in(range).iter().map(|(_, n)| n).sum::<u64>(), 1);
+
+        assert!(TimeRange::parse("from=20 to=10").is_err());
+        assert!(TimeRange::parse("until=10").is_err());
+        assert!(TimeRange::parse("to=abc").is_err());
+    }
+}
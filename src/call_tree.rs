@@ -0,0 +1,239 @@
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fmt::Write,
+};
+
+use crate::call_trace_storage::TimeRange;
+
+fn percent(n: u64, total: u64) -> f64 {
+    if total == 0 {
+        0f64
+    } else {
+        n as f64 * 100f64 / total as f64
+    }
+}
+
+/// the args of the text reports, "<limit> [sort=self|total] [min=<percent>] [from=<ms>] [to=<ms>]".
+/// the limit is the method number of the top, or the depth of the trees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReportArgs {
+    pub limit: usize,
+    pub sort_total: bool,
+    /// the tree nodes below the percent of the total are pruned.
+    pub min_percent: f64,
+    pub range: TimeRange,
+}
+
+impl ReportArgs {
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let mut tokens = args.split_whitespace();
+        let limit = tokens
+            .next()
+            .and_then(|limit| limit.parse().ok())
+            .ok_or_else(|| "the limit must be a number".to_string())?;
+        let mut report = Self {
+            limit,
+            sort_total: false,
+            min_percent: 0f64,
+            range: TimeRange::default(),
+        };
+        let mut range = Vec::new();
+        for token in tokens {
+            match token.split_once('=') {
+                Some(("sort", "self")) => report.sort_total = false,
+                Some(("sort", "total")) => report.sort_total = true,
+                Some(("sort", value)) => return Err(format!("sort must be self or total: {value}")),
+                Some(("min", value)) => {
+                    report.min_percent = value.parse().map_err(|_| format!("min must be the percent: {value}"))?;
+                }
+                _ => range.push(token),
+            }
+        }
+        report.range = TimeRange::parse(&range.join(" "))?;
+        Ok(report)
+    }
+}
+
+/// The flat profile of the frames, the self samples are the top frame's and the total samples
+/// are the traces containing the frame, the recursive frame is counted once a trace.
+pub struct FlatProfile {
+    /// (self, total) by the frame name.
+    frames: HashMap<String, (u64, u64)>,
+    total: u64,
+}
+
+impl FlatProfile {
+    pub fn new() -> Self {
+        Self {
+            frames: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    /// the stack is root first.
+    pub fn add(&mut self, stack: &[String], samples: u64) {
+        self.total += samples;
+        let mut seen = HashSet::new();
+        for name in stack {
+            if seen.insert(name) {
+                self.frames.entry(name.clone()).or_default().1 += samples;
+            }
+        }
+        if let Some(leaf) = stack.last() {
+            self.frames.entry(leaf.clone()).or_default().0 += samples;
+        }
+    }
+
+    pub fn report(&self, n: usize, sort_total: bool, out: &mut String) {
+        let mut frames: Vec<_> = self.frames.iter().collect();
+        if sort_total {
+            frames.sort_by_key(|(name, (_, total))| (Reverse(*total), *name));
+        } else {
+            frames.sort_by_key(|(name, (self_samples, _))| (Reverse(*self_samples), *name));
+        }
+        let _ = writeln!(out, "total samples: {}", self.total);
+        let _ = writeln!(out, "{:>10} {:>7} {:>10} {:>7}  name", "self", "self%", "total", "total%");
+        for (name, (self_samples, total)) in frames.into_iter().take(n) {
+            let _ = writeln!(
+                out, "{self_samples:>10} {:>6.2}% {total:>10} {:>6.2}%  {name}",
+                percent(*self_samples, self.total), percent(*total, self.total)
+            );
+        }
+    }
+}
+
+struct Node {
+    name: String,
+    samples: u64,
+    children: HashMap<String, usize>,
+}
+
+/// The call tree of the stacks, the forward tree takes the stacks root first and the
+/// reverse tree takes them leaf first, so the children of a method are its callers.
+pub struct CallTree {
+    /// the node 0 is the root of all the stacks.
+    nodes: Vec<Node>,
+}
+
+impl CallTree {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                name: String::new(),
+                samples: 0,
+                children: HashMap::new(),
+            }],
+        }
+    }
+
+    pub fn add<'a, I: IntoIterator<Item = &'a String>>(&mut self, stack: I, samples: u64) {
+        let mut idx = 0;
+        self.nodes[0].samples += samples;
+        for name in stack {
+            idx = match self.nodes[idx].children.get(name) {
+                Some(child) => *child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node {
+                        name: name.clone(),
+                        samples: 0,
+                        children: HashMap::new(),
+                    });
+                    self.nodes[idx].children.insert(name.clone(), child);
+                    child
+                }
+            };
+            self.nodes[idx].samples += samples;
+        }
+    }
+
+    /// print the nodes to the depth, the children are the most samples first.
+    pub fn report(&self, max_depth: usize, min_percent: f64, out: &mut String) {
+        let total = self.nodes[0].samples;
+        let _ = writeln!(out, "total samples: {total}");
+        let mut stack: Vec<(usize, usize)> = self.children(0).into_iter().rev().map(|c| (c, 1)).collect();
+        while let Some((idx, depth)) = stack.pop() {
+            let node = &self.nodes[idx];
+            if percent(node.samples, total) < min_percent {
+                continue;
+            }
+            let _ = writeln!(
+                out, "{:indent$}{:>6.2}% {:>8}  {}",
+                "", percent(node.samples, total), node.samples, node.name,
+                indent = (depth - 1) * 2
+            );
+            if depth < max_depth {
+                stack.extend(self.children(idx).into_iter().rev().map(|c| (c, depth + 1)));
+            }
+        }
+    }
+
+    fn children(&self, idx: usize) -> Vec<usize> {
+        let mut children: Vec<usize> = self.nodes[idx].children.values().copied().collect();
+        children.sort_by_key(|c| (Reverse(self.nodes[*c].samples), &self.nodes[*c].name));
+        children
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stacks() -> Vec<(Vec<String>, u64)> {
+        let stack = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        vec![
+            (stack(&["main", "foo", "bar"]), 6),
+            (stack(&["main", "foo"]), 2),
+            (stack(&["main", "baz", "bar"]), 2),
+        ]
+    }
+
+    #[test]
+    fn test_flat_profile() {
+        let mut flat = FlatProfile::new();
+        for (stack, samples) in stacks() {
+            flat.add(&stack, samples);
+        }
+        let mut out = String::new();
+        flat.report(2, false, &mut out);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "total samples: 10");
+        assert!(lines[2].split_whitespace().eq(["8", "80.00%", "8", "80.00%", "bar"]));
+        assert!(lines[3].split_whitespace().eq(["2", "20.00%", "8", "80.00%", "foo"]));
+        assert_eq!(lines.len(), 4);
+
+        out.clear();
+        flat.report(1, true, &mut out);
+        assert!(out.lines().nth(2).unwrap().ends_with("main"));
+    }
+
+    #[test]
+    fn test_call_tree() {
+        let mut forward = CallTree::new();
+        let mut reverse = CallTree::new();
+        for (stack, samples) in stacks() {
+            forward.add(&stack, samples);
+            reverse.add(stack.iter().rev(), samples);
+        }
+        let mut out = String::new();
+        forward.report(2, 0f64, &mut out);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[1..], ["100.00%       10  main", "   80.00%        8  foo", "   20.00%        2  baz"]);
+
+        out.clear();
+        reverse.report(3, 25f64, &mut out);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[1..], [" 80.00%        8  bar", "   60.00%        6  foo", "     60.00%        6  main"]);
+    }
+
+    #[test]
+    fn test_report_args() {
+        let args = ReportArgs::parse("5 sort=total min=1.5 from=10").unwrap();
+        assert_eq!((args.limit, args.sort_total, args.min_percent), (5, true, 1.5));
+        assert_eq!(args.range.from, Some(10_000_000));
+        assert!(ReportArgs::parse("").is_err());
+        assert!(ReportArgs::parse("5 sort=name").is_err());
+        assert!(ReportArgs::parse("5 depth=3").is_err());
+    }
+}
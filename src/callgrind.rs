@@ -0,0 +1,171 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+};
+
+/// the unknown file of the callgrind format.
+const UNKNOWN_FILE: &str = "???";
+
+/// the function and the source position of the frame, the line is 0 if unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgFrame {
+    pub function: String,
+    pub file: Option<String>,
+    pub line: u32,
+}
+
+#[derive(Default)]
+struct Call {
+    calls: u64,
+    /// the inclusive cost of the callee.
+    inclusive: u64,
+    /// the position of the callee first seen.
+    target_line: u32,
+}
+
+struct Function {
+    file: usize,
+    /// the exclusive cost by the line.
+    costs: BTreeMap<u32, u64>,
+    /// the calls by (line, callee).
+    calls: BTreeMap<(u32, usize), Call>,
+}
+
+/// Build the callgrind profile for the kcachegrind from the aggregated call traces.
+/// The exclusive cost is the samples of the top frame, the inclusive cost is the samples of
+/// the call from the caller line to the callee, the recursive call is counted once a trace.
+pub struct Callgrind {
+    functions: Vec<(String, Function)>,
+    function_ids: HashMap<String, usize>,
+    files: Vec<String>,
+    file_ids: HashMap<String, usize>,
+    total: u64,
+}
+
+impl Callgrind {
+    pub fn new() -> Self {
+        Self {
+            functions: Vec::new(),
+            function_ids: HashMap::new(),
+            files: Vec::new(),
+            file_ids: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    fn file_id(&mut self, file: Option<&str>) -> usize {
+        let file = file.unwrap_or(UNKNOWN_FILE);
+        if let Some(id) = self.file_ids.get(file) {
+            return *id;
+        }
+        self.files.push(file.to_string());
+        self.file_ids.insert(file.to_string(), self.files.len() - 1);
+        self.files.len() - 1
+    }
+
+    /// the function keeps the file first seen.
+    fn function_id(&mut self, frame: &CgFrame) -> usize {
+        if let Some(id) = self.function_ids.get(&frame.function) {
+            return *id;
+        }
+        let file = self.file_id(frame.file.as_deref());
+        let function = Function {
+            file,
+            costs: BTreeMap::new(),
+            calls: BTreeMap::new(),
+        };
+        self.functions.push((frame.function.clone(), function));
+        self.function_ids.insert(frame.function.clone(), self.functions.len() - 1);
+        self.functions.len() - 1
+    }
+
+    /// add the samples of the trace, the frames are root first.
+    pub fn add(&mut self, frames: &[CgFrame], samples: u64) {
+        let leaf = match frames.last() {
+            Some(leaf) => leaf,
+            None => return,
+        };
+        self.total += samples;
+        let ids: Vec<usize> = frames.iter().map(|f| self.function_id(f)).collect();
+        let mut seen = HashSet::new();
+        for (i, pair) in ids.windows(2).enumerate() {
+            let (caller, callee) = (pair[0], pair[1]);
+            let line = frames[i].line;
+            if !seen.insert((caller, line, callee)) {
+                continue;
+            }
+            let call = self.functions[caller].1.calls.entry((line, callee)).or_insert_with(|| Call {
+                target_line: frames[i + 1].line,
+                ..Default::default()
+            });
+            call.calls += samples;
+            call.inclusive += samples;
+        }
+        let leaf_id = ids[ids.len() - 1];
+        *self.functions[leaf_id].1.costs.entry(leaf.line).or_default() += samples;
+    }
+
+    pub fn write(&self, pid: u32, out: &mut String) {
+        let _ = writeln!(out, "# callgrind format\nversion: 1\ncreator: simple-jprofiler\npid: {pid}");
+        let _ = writeln!(out, "positions: line\nevents: Samples\nsummary: {}\n", self.total);
+        // the names are compressed, the name follows the id at the first use only.
+        let mut files_seen = vec![false; self.files.len()];
+        let mut functions_seen = vec![false; self.functions.len()];
+        let name = |out: &mut String, key: &str, seen: &mut [bool], id: usize, name: &str| {
+            if std::mem::replace(&mut seen[id], true) {
+                let _ = writeln!(out, "{key}=({})", id + 1);
+            } else {
+                let _ = writeln!(out, "{key}=({}) {name}", id + 1);
+            }
+        };
+        for (id, (function_name, function)) in self.functions.iter().enumerate() {
+            name(out, "fl", &mut files_seen, function.file, &self.files[function.file]);
+            name(out, "fn", &mut functions_seen, id, function_name);
+            for (line, cost) in function.costs.iter() {
+                let _ = writeln!(out, "{line} {cost}");
+            }
+            for ((line, callee), call) in function.calls.iter() {
+                let (callee_name, callee_function) = &self.functions[*callee];
+                let file = callee_function.file;
+                name(out, "cfi", &mut files_seen, file, &self.files[file]);
+                name(out, "cfn", &mut functions_seen, *callee, callee_name);
+                let _ = writeln!(out, "calls={} {}", call.calls, call.target_line);
+                let _ = writeln!(out, "{line} {}", call.inclusive);
+            }
+            out.push('\n');
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let frame = |function: &str, file: Option<&str>, line| CgFrame {
+            function: function.into(),
+            file: file.map(|f| f.into()),
+            line,
+        };
+        let main = frame("Main.main()V", Some("Main.java"), 5);
+        let foo = frame("Main.foo()V", Some("Main.java"), 12);
+        let memcpy = frame("memcpy", None, 0);
+        let mut callgrind = Callgrind::new();
+        callgrind.add(&[main.clone(), foo.clone()], 3);
+        callgrind.add(&[main.clone(), foo.clone(), memcpy.clone()], 2);
+        // the recursion is counted once.
+        callgrind.add(&[main.clone(), foo.clone(), main.clone(), foo.clone()], 1);
+
+        let mut out = String::new();
+        callgrind.write(42, &mut out);
+        let body: Vec<&str> = out.split("\n\n").collect();
+        assert!(body[0].ends_with("pid: 42\npositions: line\nevents: Samples\nsummary: 6"));
+        assert_eq!(body[1], "fl=(1) Main.java\nfn=(1) Main.main()V\ncfi=(1)\ncfn=(2) Main.foo()V\ncalls=6 12\n5 6");
+        assert_eq!(
+            body[2],
+            "fl=(1)\nfn=(2)\n12 4\ncfi=(1)\ncfn=(1)\ncalls=1 5\n12 1\ncfi=(2) ???\ncfn=(3) memcpy\ncalls=2 0\n12 2"
+        );
+        assert_eq!(body[3], "fl=(2)\nfn=(3)\n0 2");
+    }
+}
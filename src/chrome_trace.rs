@@ -0,0 +1,137 @@
+use std::fmt::Write;
+
+use crate::json;
+
+/// Write the chrome trace event json, loadable by chrome://tracing and ui.perfetto.dev.
+/// The samples of the thread are laid as the nested B/E slices of the frames, the other
+/// spans are the async events, so they have their own tracks and never break the nesting.
+pub struct ChromeTrace {
+    out: String,
+    pid: u32,
+    /// the nano time of the ts 0, the earlier times are clamped to it.
+    origin: u64,
+    events: usize,
+}
+
+impl ChromeTrace {
+    pub fn new(pid: u32, origin: u64) -> Self {
+        Self {
+            out: String::from("{\"traceEvents\":[\n"),
+            pid,
+            origin,
+            events: 0,
+        }
+    }
+
+    /// start the event object with the common fields, the caller closes it.
+    fn event(&mut self, ph: &str, cat: &str, name: &str, tid: u32, time: u64) {
+        if self.events > 0 {
+            self.out.push_str(",\n");
+        }
+        self.events += 1;
+        let ts = time.saturating_sub(self.origin) as f64 / 1e3;
+        let _ = write!(self.out, "{{\"ph\":\"{ph}\",\"cat\":\"{cat}\",\"name\":");
+        json::write_str(&mut self.out, name);
+        let _ = write!(self.out, ",\"pid\":{},\"tid\":{tid},\"ts\":{ts:.3}", self.pid);
+    }
+
+    pub fn process_name(&mut self, name: &str) {
+        self.event("M", "__metadata", "process_name", 0, self.origin);
+        self.out.push_str(",\"args\":{\"name\":");
+        json::write_str(&mut self.out, name);
+        self.out.push_str("}}");
+    }
+
+    pub fn thread_name(&mut self, tid: u32, name: &str) {
+        self.event("M", "__metadata", "thread_name", tid, self.origin);
+        self.out.push_str(",\"args\":{\"name\":");
+        json::write_str(&mut self.out, name);
+        self.out.push_str("}}");
+    }
+
+    /// the samples of the thread sorted by the time, the stacks are root first.
+    /// a sample lasts until the next sample, at most the sample period, the slices are
+    /// closed at the end if the thread ends before it.
+    pub fn thread_samples(&mut self, tid: u32, samples: &[(u64, &[String])], period: u64, end: Option<u64>) {
+        let mut open: Vec<&str> = Vec::new();
+        for (idx, (time, stack)) in samples.iter().enumerate() {
+            let common = open.iter().zip(stack.iter()).take_while(|(a, b)| **a == b.as_str()).count();
+            while open.len() > common {
+                let name = open.pop().unwrap_or_default();
+                self.event("E", "sample", name, tid, *time);
+                self.out.push('}');
+            }
+            for name in stack[common..].iter() {
+                self.event("B", "sample", name, tid, *time);
+                self.out.push('}');
+                open.push(name);
+            }
+            let sample_end = time + period;
+            if samples.get(idx + 1).is_some_and(|(next, _)| *next <= sample_end) {
+                continue;
+            }
+            let sample_end = end.map_or(sample_end, |end| end.clamp(*time, sample_end));
+            while let Some(name) = open.pop() {
+                self.event("E", "sample", name, tid, sample_end);
+                self.out.push('}');
+            }
+        }
+    }
+
+    /// the span on the track of its own.
+    pub fn complete(&mut self, tid: u32, cat: &str, name: &str, start: u64, duration: u64) {
+        self.event("X", cat, name, tid, start);
+        let _ = write!(self.out, ",\"dur\":{:.3}}}", duration as f64 / 1e3);
+    }
+
+    /// the async span of the thread, the spans of the same cat are matched by the id.
+    pub fn async_span(&mut self, tid: u32, cat: &str, id: u64, name: &str, start: u64, end: u64) {
+        for (ph, time) in [("b", start), ("e", end.max(start))] {
+            self.event(ph, cat, name, tid, time);
+            let _ = write!(self.out, ",\"id\":\"0x{id:x}\"}}");
+        }
+    }
+
+    pub fn finish(mut self) -> String {
+        self.out.push_str("\n],\"displayTimeUnit\":\"ms\"}\n");
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_thread_samples() {
+        let mut trace = ChromeTrace::new(1, 1_000);
+        trace.thread_name(7, "main \"worker\"");
+        let a: Vec<String> = vec!["main".into(), "foo".into()];
+        let b: Vec<String> = vec!["main".into(), "bar".into()];
+        let samples = [(2_000, &a[..]), (12_000, &b[..]), (60_000, &b[..])];
+        trace.thread_samples(7, &samples, 10_000, Some(65_000));
+        trace.async_span(7, "lock", 1, "monitor java.lang.Object", 3_000, 5_000);
+        let out = trace.finish();
+
+        let events: Vec<&str> = out.lines().filter(|l| l.starts_with('{') && l.contains("\"ph\"")).collect();
+        let ph: Vec<(&str, &str)> = events
+            .iter()
+            .map(|e| {
+                let field = |key: &str| {
+                    let start = e.find(key).unwrap() + key.len();
+                    e[start..].split([',', '}']).next().unwrap()
+                };
+                (field("\"ph\":"), field("\"ts\":"))
+            })
+            .collect();
+        assert_eq!(ph[0], ("\"M\"", "0.000"));
+        assert!(events[0].contains(r#""name":"main \"worker\"""#));
+        // main and foo begin, foo ends and bar begins at the next sample.
+        assert_eq!(&ph[1..5], &[("\"B\"", "1.000"), ("\"B\"", "1.000"), ("\"E\"", "11.000"), ("\"B\"", "11.000")]);
+        // the gap to the last sample closes all, the last sample ends at the thread end.
+        assert_eq!(&ph[5..7], &[("\"E\"", "21.000"), ("\"E\"", "21.000")]);
+        assert_eq!(&ph[9..11], &[("\"E\"", "64.000"), ("\"E\"", "64.000")]);
+        assert_eq!(&ph[11..], &[("\"b\"", "2.000"), ("\"e\"", "4.000")]);
+        assert!(out.ends_with("],\"displayTimeUnit\":\"ms\"}\n"));
+    }
+}
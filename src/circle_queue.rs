@@ -15,14 +15,20 @@ const FRAME_SIZE: usize = 1024;
 #[derive(Default)]
 pub struct CallTraceHolder {
     pub trace: JVMPICallTrace,
+    /// the monotonic nano time of the sample.
+    pub time: u64,
+    /// the os thread id of the sampled thread.
+    pub tid: u32,
     pub is_commit: AtomicBool,
 }
 
 impl CallTraceHolder {
     #[inline(always)]
-    pub fn new(holder: &JVMPICallTrace) -> Self {
+    pub fn new(holder: &JVMPICallTrace, time: u64, tid: u32) -> Self {
         Self {
             trace: *holder,
+            time,
+            tid,
             is_commit: AtomicBool::new(false),
         }
     }
@@ -39,7 +45,7 @@ impl Drop for CircleQueue {
     fn drop(&mut self) {
         unsafe {
             Self::dealloc_array::<CallTraceHolder>(self.holders as _, HOLDER_SIZE);
-            Self::dealloc_array::<*mut [JVMPICallFrame; FRAME_SIZE]>(self.frames as _, HOLDER_SIZE);
+            Self::dealloc_array::<[JVMPICallFrame; FRAME_SIZE]>(self.frames as _, HOLDER_SIZE);
         }
     }
 }
@@ -116,8 +122,10 @@ impl CircleQueue {
         *holder_mut = holder;
     }
 
-    pub fn push(&mut self, trace: &JVMPICallTrace) -> bool {
-        let holder = CallTraceHolder::new(&trace);
+    /// push the trace, the frames are copied into the queue, so the frame buffer of
+    /// the trace can be reused after push.
+    pub fn push(&mut self, trace: &JVMPICallTrace, time: u64, tid: u32) -> bool {
+        let mut holder = CallTraceHolder::new(&trace, time, tid);
         let mut i_idx;
         let mut next_i_idx;
         let mut o_idx;
@@ -137,6 +145,15 @@ impl CircleQueue {
                 break;
             }
         }
+        let num_frames = (trace.num_frames.max(0) as usize).min(FRAME_SIZE);
+        let frames = self.frames_mut(i_idx);
+        if num_frames > 0 {
+            unsafe {
+                std::ptr::copy_nonoverlapping(trace.frames, frames.as_mut_ptr(), num_frames);
+            }
+        }
+        holder.trace.frames = frames.as_mut_ptr();
+        holder.trace.num_frames = num_frames as _;
         self.write_handle(i_idx, holder);
         self.holders_mut(i_idx)
             .is_commit
@@ -144,7 +161,8 @@ impl CircleQueue {
         true
     }
 
-    pub fn pop(&mut self) -> bool {
+    /// pop the trace with its time and tid, the frames of the trace is only valid in the callback.
+    pub fn pop<F: FnOnce(&JVMPICallTrace, u64, u32)>(&mut self, f: F) -> bool {
         let o_idx = self.o_idx.load(Ordering::Relaxed);
         let i_idx = self.i_idx.load(Ordering::Acquire);
         if o_idx == i_idx {
@@ -153,6 +171,8 @@ impl CircleQueue {
         while !self.holders(o_idx).is_commit.load(Ordering::Acquire) {
             std::thread::sleep(Duration::from_micros(1));
         }
+        let holder = self.holders(o_idx);
+        f(&holder.trace, holder.time, holder.tid);
         self.holders(o_idx)
             .is_commit
             .store(false, Ordering::Release);
@@ -1,49 +1,47 @@
 use std::cmp::Ordering;
 use std::ffi::CStr;
 use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Mutex;
+
+use crate::dwarf::{FrameDesc, DEFAULT_FRAME};
 
 const NO_MIN_ADDRESS: *const i8 = -1 as _;
 const NO_MAX_ADDRESS: *const i8 = 0 as _;
 const INITIAL_CODE_CACHE_CAPACITY: usize = 1024;
 
 pub struct NativeFunc {
-    lib_index: u16,
-    mark: bool,
+    /// the name with the nul terminator, the heap bytes don't move with the func.
     name: Vec<u8>,
 }
 
 impl NativeFunc {
-    pub fn create(name: *const i8, lib_index: u16) -> Self {
-        let name = unsafe { CStr::from_ptr(name).to_bytes() };
-        let name = name.into();
-        Self {
-            lib_index,
-            mark: false,
-            name,
-        }
-    }
-
-    pub fn mark(&mut self) {
-        self.mark = true;
-    }
-
-    pub fn is_mark(&self) -> bool {
-        self.mark
+    /// # Safety
+    /// the name must be a valid nul terminated string.
+    pub(crate) unsafe fn create(name: *const i8) -> Self {
+        let name = CStr::from_ptr(name).to_bytes_with_nul();
+        Self { name: name.into() }
     }
 
     #[inline(always)]
     pub fn name(&self) -> &[u8] {
-        &self.name
+        &self.name[..self.name.len() - 1]
     }
 
     #[inline(always)]
     pub fn name_str(&self) -> &str {
-        unsafe { std::str::from_utf8_unchecked(&self.name) }
+        unsafe { std::str::from_utf8_unchecked(self.name()) }
     }
 
     #[inline(always)]
     pub fn name_mut(&mut self) -> &mut [u8] {
-        &mut self.name
+        let len = self.name.len() - 1;
+        &mut self.name[..len]
+    }
+
+    #[inline(always)]
+    pub fn c_name(&self) -> *const i8 {
+        self.name.as_ptr() as _
     }
 }
 
@@ -63,6 +61,22 @@ impl CodeBlob {
         self.name.name_str()
     }
 
+    /// the nul terminated name, it's kept valid when the blob is moved by the sort.
+    #[inline(always)]
+    pub fn c_name(&self) -> *const i8 {
+        self.name.c_name()
+    }
+
+    #[inline(always)]
+    pub fn start(&self) -> *const i8 {
+        self.start
+    }
+
+    #[inline(always)]
+    pub fn size(&self) -> usize {
+        self.end as usize - self.start as usize
+    }
+
     fn cmp(&self, other: &Self) -> Ordering {
         if self.start < other.start {
             Ordering::Less
@@ -86,15 +100,22 @@ pub struct CodeCache {
     pub(crate) min_address: *const i8,
     pub(crate) max_address: *const i8,
     text_base: *const i8,
+    /// the ET_EXEC image is loaded at its link address, it's not relocated by the text base.
+    exec_image: bool,
     got_start: *const *const i8,
     got_end: *const *const i8,
     got_patchable: bool,
     debug_symbols: bool,
     blobs: Vec<CodeBlob>,
+    dwarf_table: Vec<FrameDesc>,
+    /// the file has the .debug_line section, the library itself or the separate debug file.
+    debug_file: Option<String>,
 }
 
 impl CodeCache {
-    pub fn new(name: *const i8, lib_index: u16) -> Self {
+    /// # Safety
+    /// the name must be a valid NUL-terminated string.
+    pub unsafe fn new(name: *const i8, lib_index: u16) -> Self {
         Self::new_with_address_range(name, lib_index, NO_MIN_ADDRESS, NO_MAX_ADDRESS)
     }
 
@@ -105,24 +126,29 @@ impl CodeCache {
             .map(|blob| blob.start)
     }
 
-    pub fn new_with_address_range(
+    /// # Safety
+    /// the name must be a valid NUL-terminated string.
+    pub unsafe fn new_with_address_range(
         name: *const i8,
         lib_index: u16,
         min_address: *const i8,
         max_address: *const i8,
     ) -> Self {
-        let name = NativeFunc::create(name, lib_index);
+        let name = NativeFunc::create(name);
         Self {
             name,
             lib_index,
             min_address,
             max_address,
             text_base: ptr::null(),
+            exec_image: false,
             got_start: ptr::null(),
             got_end: ptr::null(),
             got_patchable: false,
             debug_symbols: false,
             blobs: Vec::with_capacity(INITIAL_CODE_CACHE_CAPACITY),
+            dwarf_table: Vec::new(),
+            debug_file: None,
         }
     }
 
@@ -130,14 +156,16 @@ impl CodeCache {
         self.debug_symbols = b;
     }
 
-    pub fn add(
+    /// # Safety
+    /// the name must be a valid NUL-terminated string.
+    pub unsafe fn add(
         &mut self,
         start: *const i8,
         length: usize,
         name: *const i8,
         is_update_bounds: bool,
     ) {
-        let mut name = NativeFunc::create(name, self.lib_index);
+        let mut name = NativeFunc::create(name);
         for val in name.name_mut() {
             if *val < b' ' {
                 *val = b'?';
@@ -195,8 +223,9 @@ impl CodeCache {
             Ok(pos) => return self.blobs.get(pos),
             Err(low) => low,
         };
-        if low > 0 && self.blobs[low - 1].start == self.blobs[low - 1].end
-            || self.blobs[low - 1].end == addr
+        if low > 0
+            && (self.blobs[low - 1].start == self.blobs[low - 1].end
+                || self.blobs[low - 1].end == addr)
         {
             return self.blobs.get(low - 1);
         }
@@ -208,6 +237,42 @@ impl CodeCache {
         self.text_base = text_base;
     }
 
+    #[inline(always)]
+    pub fn text_base(&self) -> *const i8 {
+        self.text_base
+    }
+
+    #[inline(always)]
+    pub fn set_exec_image(&mut self, exec_image: bool) {
+        self.exec_image = exec_image;
+    }
+
+    /// the address of the pc in the debug info, relative to the text base unless the
+    /// image is ET_EXEC, whose debug info has the absolute addresses.
+    #[inline(always)]
+    pub fn link_address(&self, pc: *const i8) -> u64 {
+        if self.exec_image {
+            pc as u64
+        } else {
+            (pc as u64).wrapping_sub(self.text_base as u64)
+        }
+    }
+
+    #[inline(always)]
+    pub fn lib_index(&self) -> u16 {
+        self.lib_index
+    }
+
+    #[inline(always)]
+    pub fn set_debug_file(&mut self, file: &str) {
+        self.debug_file = Some(file.to_string());
+    }
+
+    #[inline(always)]
+    pub fn debug_file(&self) -> Option<&str> {
+        self.debug_file.as_deref()
+    }
+
     pub fn find_symbol_prefix(&self, name: &[u8]) -> Option<*const i8> {
         self.blobs
             .iter()
@@ -220,6 +285,29 @@ impl CodeCache {
         addr >= self.min_address && addr < self.max_address
     }
 
+    /// the table is sorted by the loc, which is relative to the text base.
+    #[inline(always)]
+    pub fn set_dwarf_table(&mut self, table: Vec<FrameDesc>) {
+        self.dwarf_table = table;
+    }
+
+    #[inline(always)]
+    pub fn dwarf_table(&self) -> &[FrameDesc] {
+        &self.dwarf_table
+    }
+
+    /// find the unwind rule of the pc, the frame pointer rule is returned if the pc
+    /// isn't covered by the dwarf table.
+    pub fn find_frame_desc(&self, pc: *const i8) -> &FrameDesc {
+        let target_loc = (pc as usize).wrapping_sub(self.text_base as usize) as u32;
+        let idx = self.dwarf_table.partition_point(|f| f.loc <= target_loc);
+        if idx > 0 {
+            &self.dwarf_table[idx - 1]
+        } else {
+            &DEFAULT_FRAME
+        }
+    }
+
     pub fn set_global_offset_table(
         &mut self,
         start: *const *const i8,
@@ -232,6 +320,249 @@ impl CodeCache {
     }
 }
 
+/// the address range of the code cache in the index.
+#[derive(Clone, Copy)]
+struct AddressRange {
+    start: usize,
+    end: usize,
+    /// the max end of the ranges before this one, the ranges may overlap.
+    max_end: usize,
+    idx: usize,
+}
+
+/// The ranges of the code caches sorted by the start address.
+struct AddressIndex {
+    ranges: Box<[AddressRange]>,
+    /// the number of the code caches covered by the index.
+    count: usize,
+    /// the number of the removed code caches when the index is built.
+    removed: usize,
+}
+
+impl AddressIndex {
+    fn build<'a>(libs: impl Iterator<Item = (usize, &'a CodeCache)>, count: usize, removed: usize) -> Self {
+        let mut ranges: Vec<AddressRange> = libs
+            .filter(|(_, cc)| cc.min_address < cc.max_address)
+            .map(|(idx, cc)| AddressRange {
+                start: cc.min_address as _,
+                end: cc.max_address as _,
+                max_end: 0,
+                idx,
+            })
+            .collect();
+        ranges.sort_by_key(|r| r.start);
+        let mut max_end = 0;
+        for r in ranges.iter_mut() {
+            max_end = max_end.max(r.end);
+            r.max_end = max_end;
+        }
+        Self {
+            ranges: ranges.into_boxed_slice(),
+            count,
+            removed,
+        }
+    }
+
+    /// binary search the last range start before the addr, then walk back while
+    /// the overlapped ranges can still contain the addr.
+    fn find(&self, addr: usize) -> Option<usize> {
+        let pos = self.ranges.partition_point(|r| r.start <= addr);
+        for r in self.ranges[..pos].iter().rev() {
+            if r.max_end <= addr {
+                break;
+            }
+            if addr < r.end {
+                return Some(r.idx);
+            }
+        }
+        None
+    }
+}
+
+/// the code found by the address.
+pub enum CodeLookup<'a> {
+    Library(&'a CodeCache),
+    /// the runtime stubs are updated at runtime, so they are kept by the owner under its lock.
+    RuntimeStubs,
+}
+
+/// The append-only array of the code caches, read by the signal handler without lock.
+/// The code cache is boxed so it's never moved, the slot is filled before the count is
+/// published, so the readers always see the fully parsed code caches.
+/// Only one writer is allowed, the SymbolParser serializes the adds by its mutex.
+/// The removed code cache leaves an empty slot, so the slot index of the others is kept.
+pub struct CodeCacheArray {
+    libs: Box<[AtomicPtr<CodeCache>]>,
+    count: AtomicUsize,
+    /// the sorted address index, rebuilt by `update_index`. the replaced indexes
+    /// are kept until drop, the signal handler may still read them.
+    index: AtomicPtr<AddressIndex>,
+    retired: Mutex<Vec<*mut AddressIndex>>,
+    /// the code caches of the unloaded libraries, kept until drop for the same reason.
+    removed: Mutex<Vec<*mut CodeCache>>,
+    removed_count: AtomicUsize,
+    /// the address range of the runtime stubs.
+    stub_start: AtomicUsize,
+    stub_end: AtomicUsize,
+}
+
+impl CodeCacheArray {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            libs: (0..capacity).map(|_| AtomicPtr::new(ptr::null_mut())).collect(),
+            count: AtomicUsize::new(0),
+            index: AtomicPtr::new(ptr::null_mut()),
+            retired: Mutex::new(Vec::new()),
+            removed: Mutex::new(Vec::new()),
+            removed_count: AtomicUsize::new(0),
+            stub_start: AtomicUsize::new(0),
+            stub_end: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline(always)]
+    pub fn count(&self) -> usize {
+        self.count.load(AtomicOrdering::Acquire)
+    }
+
+    #[inline(always)]
+    pub fn is_full(&self) -> bool {
+        self.count() >= self.libs.len()
+    }
+
+    /// publish the code cache, return false if the array is full.
+    /// the code cache is found by the linear scan until the index is updated.
+    pub fn add(&self, cc: CodeCache) -> bool {
+        let idx = self.count.load(AtomicOrdering::Relaxed);
+        if idx >= self.libs.len() {
+            return false;
+        }
+        self.libs[idx].store(Box::into_raw(Box::new(cc)), AtomicOrdering::Relaxed);
+        self.count.store(idx + 1, AtomicOrdering::Release);
+        true
+    }
+
+    /// unpublish the code cache of the unloaded library, the slot is left empty.
+    /// the index still has its range until the next `update_index`.
+    pub fn remove(&self, idx: usize) {
+        if idx >= self.count() {
+            return;
+        }
+        let cc = self.libs[idx].swap(ptr::null_mut(), AtomicOrdering::AcqRel);
+        if cc.is_null() {
+            return;
+        }
+        if let Ok(mut removed) = self.removed.lock() {
+            removed.push(cc);
+        }
+        self.removed_count.fetch_add(1, AtomicOrdering::Release);
+    }
+
+    /// rebuild the address index after a batch of the code caches is added or removed.
+    pub fn update_index(&self) {
+        let count = self.count();
+        let removed = self.removed_count.load(AtomicOrdering::Acquire);
+        let index = self.index.load(AtomicOrdering::Acquire);
+        if unsafe { index.as_ref() }.is_some_and(|i| i.count == count && i.removed == removed) {
+            return;
+        }
+        let new_index = Box::into_raw(Box::new(AddressIndex::build(self.entries(count), count, removed)));
+        let old = self.index.swap(new_index, AtomicOrdering::AcqRel);
+        if !old.is_null() {
+            if let Ok(mut retired) = self.retired.lock() {
+                retired.push(old);
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn lib(&self, idx: usize) -> Option<&CodeCache> {
+        unsafe { self.libs[idx].load(AtomicOrdering::Acquire).as_ref() }
+    }
+
+    /// the published code caches with their slot index.
+    fn entries(&self, count: usize) -> impl Iterator<Item = (usize, &CodeCache)> {
+        self.libs[..count]
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, p)| unsafe { p.load(AtomicOrdering::Acquire).as_ref() }.map(|cc| (idx, cc)))
+    }
+
+    /// the snapshot of the published code caches.
+    #[cfg(any(test, feature = "bench"))]
+    pub fn iter(&self) -> impl Iterator<Item = &CodeCache> {
+        self.entries(self.count()).map(|(_, cc)| cc)
+    }
+
+    /// find the code cache by the index, the code caches added after the index built
+    /// are scanned linearly. if the ranges overlap, the one starts closest to the addr wins.
+    pub fn find(&self, addr: *const i8) -> Option<&CodeCache> {
+        let count = self.count();
+        let index = unsafe { self.index.load(AtomicOrdering::Acquire).as_ref() };
+        let indexed = index.map_or(0, |i| i.count.min(count));
+        // the removed code cache is skipped until the index is rebuilt.
+        if let Some(cc) = index.and_then(|i| i.find(addr as _)).and_then(|idx| self.lib(idx)) {
+            return Some(cc);
+        }
+        self.libs[indexed..count]
+            .iter()
+            .filter_map(|p| unsafe { p.load(AtomicOrdering::Relaxed).as_ref() })
+            .find(|cc| cc.contains(addr))
+    }
+
+    /// the stubs are only added by the owner of the runtime stubs, the range only grows.
+    pub fn set_stub_range(&self, start: *const i8, end: *const i8) {
+        self.stub_start.store(start as _, AtomicOrdering::Release);
+        self.stub_end.store(end as _, AtomicOrdering::Release);
+    }
+
+    /// find the library or the runtime stubs by the addr, the libraries go first.
+    pub fn lookup(&self, addr: *const i8) -> Option<CodeLookup<'_>> {
+        if let Some(cc) = self.find(addr) {
+            return Some(CodeLookup::Library(cc));
+        }
+        let start = self.stub_start.load(AtomicOrdering::Acquire);
+        let end = self.stub_end.load(AtomicOrdering::Acquire);
+        (start <= addr as usize && (addr as usize) < end).then_some(CodeLookup::RuntimeStubs)
+    }
+
+    /// the linear scan, same as the `find` without the index, the baseline of the bench.
+    #[cfg(feature = "bench")]
+    pub fn find_linear(&self, addr: *const i8) -> Option<&CodeCache> {
+        self.iter().find(|cc| cc.contains(addr))
+    }
+}
+
+impl Drop for CodeCacheArray {
+    fn drop(&mut self) {
+        let count = *self.count.get_mut();
+        for p in self.libs[..count].iter_mut() {
+            let cc = *p.get_mut();
+            if !cc.is_null() {
+                drop(unsafe { Box::from_raw(cc) });
+            }
+        }
+        let index = *self.index.get_mut();
+        if !index.is_null() {
+            drop(unsafe { Box::from_raw(index) });
+        }
+        if let Ok(retired) = self.retired.get_mut() {
+            for index in retired.drain(..) {
+                drop(unsafe { Box::from_raw(index) });
+            }
+        }
+        if let Ok(removed) = self.removed.get_mut() {
+            for cc in removed.drain(..) {
+                drop(unsafe { Box::from_raw(cc) });
+            }
+        }
+    }
+}
+
+unsafe impl Sync for CodeCacheArray {}
+
+unsafe impl Send for CodeCacheArray {}
+
 #[allow(unused)]
 mod test {
     use super::*;
@@ -239,12 +570,108 @@ mod test {
 
     #[test]
     fn test_sort() {
-        let mut code_cache = CodeCache::new(c_str!("test") as _, 1);
-        code_cache.add(120 as _, 10, c_str!("test1") as _, true);
-        code_cache.add(100 as _, 10, c_str!("test1") as _, true);
-        code_cache.add(140 as _, 10, c_str!("test1") as _, true);
+        let mut code_cache = unsafe { CodeCache::new(c_str!("test") as _, 1) };
+        unsafe { code_cache.add(120 as _, 10, c_str!("test1") as _, true) };
+        unsafe { code_cache.add(100 as _, 10, c_str!("test1") as _, true) };
+        unsafe { code_cache.add(140 as _, 10, c_str!("test1") as _, true) };
         code_cache.sort();
         assert_eq!(code_cache.min_address, 100 as _);
         assert_eq!(code_cache.max_address, 150 as _);
     }
+
+    #[test]
+    fn test_code_cache_array() {
+        let array = CodeCacheArray::new(2);
+        assert!(array.find(105 as _).is_none());
+        let mut cc = unsafe { CodeCache::new(c_str!("lib1") as _, 0) };
+        unsafe { cc.add(100 as _, 10, c_str!("f1") as _, true) };
+        assert!(array.add(cc));
+        assert!(array.add(unsafe { CodeCache::new(c_str!("lib2") as _, 1) }));
+        assert!(!array.add(unsafe { CodeCache::new(c_str!("lib3") as _, 2) }));
+        assert!(array.is_full());
+        assert_eq!(array.count(), 2);
+        assert_eq!(array.find(105 as _).map(|cc| cc.name_str()), Some("lib1"));
+        assert_eq!(array.iter().nth(1).map(|cc| cc.name_str()), Some("lib2"));
+    }
+
+    #[test]
+    fn test_address_index() {
+        let array = CodeCacheArray::new(8);
+        let lib = |name, start: usize, end: usize| {
+            unsafe { CodeCache::new_with_address_range(name, 0, start as _, end as _) }
+        };
+        array.add(lib(c_str!("lib1"), 300, 400));
+        array.add(lib(c_str!("lib2"), 100, 200));
+        // overlap with the lib2
+        array.add(lib(c_str!("lib3"), 150, 160));
+        array.add(unsafe { CodeCache::new(c_str!("empty"), 0) });
+        array.update_index();
+        array.add(lib(c_str!("lib4"), 500, 600));
+        let name = |addr: usize| array.find(addr as _).map(|cc| cc.name_str());
+        assert_eq!(name(99), None);
+        assert_eq!(name(100), Some("lib2"));
+        assert_eq!(name(170), Some("lib2"));
+        assert_eq!(name(155), Some("lib3"));
+        assert_eq!(name(200), None);
+        assert_eq!(name(399), Some("lib1"));
+        assert_eq!(name(550), Some("lib4"));
+        array.update_index();
+        assert_eq!(name(550), Some("lib4"));
+        assert_eq!(name(600), None);
+
+        array.set_stub_range(1000 as _, 1100 as _);
+        assert!(matches!(array.lookup(1050 as _), Some(CodeLookup::RuntimeStubs)));
+        assert!(matches!(array.lookup(150 as _), Some(CodeLookup::Library(_))));
+        assert!(array.lookup(1100 as _).is_none());
+        assert!(array.find(1050 as _).is_none());
+
+        // the unloaded lib2, lib3 in its range is still found.
+        array.remove(1);
+        assert_eq!(name(170), None);
+        assert_eq!(name(155), Some("lib3"));
+        array.update_index();
+        assert_eq!(name(170), None);
+        assert_eq!(name(155), Some("lib3"));
+        assert_eq!(name(550), Some("lib4"));
+        assert_eq!(array.iter().count(), 4);
+        assert_eq!(array.count(), 5);
+    }
+
+    #[test]
+    fn test_sorted_stubs() {
+        let mut code_cache = unsafe { CodeCache::new(c_str!("[stubs]") as _, 1) };
+        unsafe { code_cache.add(120 as _, 10, c_str!("stub1") as _, true) };
+        let name = code_cache.code_blobs()[0].c_name();
+        unsafe { code_cache.add(100 as _, 10, c_str!("stub2") as _, true) };
+        code_cache.sort();
+        assert_eq!(code_cache.binary_search(105 as _).map(|b| b.name_str()), Some("stub2"));
+        assert_eq!(code_cache.binary_search(129 as _).map(|b| b.name_str()), Some("stub1"));
+        assert!(code_cache.binary_search(99 as _).is_none());
+        assert!(code_cache.binary_search(115 as _).is_none());
+        // the name of the moved blob is still valid.
+        assert_eq!(code_cache.binary_search(120 as _).unwrap().c_name(), name);
+        assert_eq!(unsafe { CStr::from_ptr(name) }.to_bytes(), b"stub1");
+    }
+
+    #[test]
+    fn test_find_frame_desc() {
+        let mut code_cache = unsafe { CodeCache::new(c_str!("test") as _, 1) };
+        code_cache.set_text_base(0x1000 as _);
+        assert_eq!(code_cache.find_frame_desc(0x1010 as _), &DEFAULT_FRAME);
+        let frame = |loc| FrameDesc { loc, cfa: 7 | 8 << 8, fp_off: 0 };
+        code_cache.set_dwarf_table(vec![frame(0x10), frame(0x20)]);
+        assert_eq!(code_cache.find_frame_desc(0x1008 as _), &DEFAULT_FRAME);
+        assert_eq!(code_cache.find_frame_desc(0x1010 as _).loc, 0x10);
+        assert_eq!(code_cache.find_frame_desc(0x101f as _).loc, 0x10);
+        assert_eq!(code_cache.find_frame_desc(0x1030 as _).loc, 0x20);
+    }
+
+    #[test]
+    fn test_link_address() {
+        let mut code_cache = unsafe { CodeCache::new(c_str!("test") as _, 1) };
+        code_cache.set_text_base(0x1000 as _);
+        assert_eq!(code_cache.link_address(0x1010 as _), 0x10);
+        code_cache.set_exec_image(true);
+        assert_eq!(code_cache.link_address(0x1010 as _), 0x1010);
+    }
 }
@@ -1,13 +1,16 @@
-use std::io::Read;
+use std::io::{Read, Write};
 use std::mem;
 use std::ptr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::{net::TcpListener, os::fd::AsRawFd};
+use std::{net::{TcpListener, TcpStream}, os::fd::AsRawFd};
 
 use crate::c_str;
+use crate::call_trace_storage::TimeRange;
+use crate::call_tree::ReportArgs;
+use crate::frame_name::{Demangle, FrameDetail};
 use crate::jvmti::{JNIEnv, JVMTI_THREAD_NORM_PRIORITY};
 use crate::vm::VM;
-use crate::{get_vm_mut, log_info};
+use crate::{get_vm, get_vm_mut, log_info};
 
 pub struct CtrlSvr {
     listener: TcpListener,
@@ -48,6 +51,41 @@ impl CtrlSvr {
         );
     }
 
+    /// the on/off switch command like perfmap=on.
+    fn switch<F>(peer_stream: &mut TcpStream, cmd: &str, value: &str, f: F)
+    where
+        F: FnOnce(bool) -> std::io::Result<()>,
+    {
+        let result = match value.trim() {
+            "on" => f(true),
+            "off" => f(false),
+            _ => {
+                let _ = writeln!(peer_stream, "{cmd} must be on or off");
+                return;
+            }
+        };
+        if let Err(e) = result {
+            let _ = writeln!(peer_stream, "{cmd} fail: {e}");
+        }
+    }
+
+    /// the text report command like top=10 sort=total.
+    fn report<F>(peer_stream: &mut TcpStream, args: &str, f: F)
+    where
+        F: FnOnce(ReportArgs, &mut String),
+    {
+        match ReportArgs::parse(args) {
+            Ok(args) => {
+                let mut out = String::new();
+                f(args, &mut out);
+                let _ = peer_stream.write_all(out.as_bytes());
+            }
+            Err(e) => {
+                let _ = writeln!(peer_stream, "{e}");
+            }
+        }
+    }
+
     pub fn run(&mut self) {
         log_info!("INFO: control svr start.");
         self.running.store(true, Ordering::Relaxed);
@@ -64,6 +102,156 @@ impl CtrlSvr {
                     get_vm_mut().stop_prof()
                 }
 
+                if let Some(detail) = cmd.strip_prefix("detail=") {
+                    match FrameDetail::parse(detail) {
+                        Some(detail) => get_vm_mut().profiler_mut().set_frame_detail(detail),
+                        None => {
+                            let _ = peer_stream.write_all(b"detail must be method, line or bci\n");
+                        }
+                    }
+                }
+
+                if let Some(demangle) = cmd.strip_prefix("demangle=") {
+                    match Demangle::parse(demangle) {
+                        Some(demangle) => get_vm_mut().profiler_mut().set_demangle(demangle),
+                        None => {
+                            let _ = peer_stream.write_all(b"demangle flags must be hash, nohash, params or noparams\n");
+                        }
+                    }
+                }
+
+                if let Some(value) = cmd.strip_prefix("kernel=") {
+                    Self::switch(&mut peer_stream, "kernel", value, |on| {
+                        get_vm_mut().profiler_mut().set_kernel_symbols(on);
+                        Ok(())
+                    });
+                }
+
+                if let Some(value) = cmd.strip_prefix("perfmap=") {
+                    Self::switch(&mut peer_stream, "perfmap", value, |on| get_vm().profiler().enable_perf_map(on));
+                }
+
+                if let Some(value) = cmd.strip_prefix("jitdump=") {
+                    Self::switch(&mut peer_stream, "jitdump", value, |on| get_vm().profiler().enable_jitdump(on));
+                }
+
+                if cmd.starts_with("status") {
+                    let mut out = String::new();
+                    get_vm_mut().profiler().status(&mut out);
+                    let _ = peer_stream.write_all(out.as_bytes());
+                }
+
+                if let Some(args) = cmd.strip_prefix("dump") {
+                    match TimeRange::parse(args) {
+                        Ok(range) => {
+                            let mut out = String::new();
+                            get_vm_mut().profiler().dump_collapsed(range, &mut out);
+                            let _ = peer_stream.write_all(out.as_bytes());
+                        }
+                        Err(e) => {
+                            let _ = writeln!(peer_stream, "{e}");
+                        }
+                    }
+                }
+
+                if let Some(args) = cmd.strip_prefix("trace") {
+                    match TimeRange::parse(args) {
+                        Ok(range) => {
+                            let mut out = String::new();
+                            get_vm().profiler().dump_chrome_trace(range, &mut out);
+                            let _ = peer_stream.write_all(out.as_bytes());
+                        }
+                        Err(e) => {
+                            let _ = writeln!(peer_stream, "{e}");
+                        }
+                    }
+                }
+
+                if let Some(args) = cmd.strip_prefix("speedscope") {
+                    match TimeRange::parse(args) {
+                        Ok(range) => {
+                            let mut out = String::new();
+                            get_vm().profiler().dump_speedscope(range, &mut out);
+                            let _ = peer_stream.write_all(out.as_bytes());
+                        }
+                        Err(e) => {
+                            let _ = writeln!(peer_stream, "{e}");
+                        }
+                    }
+                }
+
+                if let Some(args) = cmd.strip_prefix("callgrind") {
+                    match TimeRange::parse(args) {
+                        Ok(range) => {
+                            let mut out = String::new();
+                            get_vm().profiler().dump_callgrind(range, &mut out);
+                            let _ = peer_stream.write_all(out.as_bytes());
+                        }
+                        Err(e) => {
+                            let _ = writeln!(peer_stream, "{e}");
+                        }
+                    }
+                }
+
+                if let Some(args) = cmd.strip_prefix("top=") {
+                    Self::report(&mut peer_stream, args, |args, out| get_vm().profiler().dump_top(args, out));
+                }
+
+                if let Some(args) = cmd.strip_prefix("tree=") {
+                    Self::report(&mut peer_stream, args, |args, out| {
+                        get_vm().profiler().dump_call_tree(args, false, out)
+                    });
+                }
+
+                if let Some(args) = cmd.strip_prefix("callers=") {
+                    Self::report(&mut peer_stream, args, |args, out| {
+                        get_vm().profiler().dump_call_tree(args, true, out)
+                    });
+                }
+
+                if let Some(value) = cmd.strip_prefix("pcs=") {
+                    Self::switch(&mut peer_stream, "pcs", value, |on| {
+                        get_vm().profiler().enable_pc_histogram(on);
+                        Ok(())
+                    });
+                }
+
+                if let Some(pattern) = cmd.strip_prefix("hot=") {
+                    let mut out = String::new();
+                    get_vm().profiler().dump_hot_pcs(pattern.trim(), &mut out);
+                    let _ = peer_stream.write_all(out.as_bytes());
+                }
+
+                if let Some(args) = cmd.strip_prefix("lines=") {
+                    let (top, range) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+                    match (top.parse(), TimeRange::parse(range)) {
+                        (Ok(top), Ok(range)) => {
+                            let mut out = String::new();
+                            get_vm().profiler().dump_hot_lines(top, range, &mut out);
+                            let _ = peer_stream.write_all(out.as_bytes());
+                        }
+                        (Err(_), _) => {
+                            let _ = peer_stream.write_all(b"lines must be the number of methods\n");
+                        }
+                        (_, Err(e)) => {
+                            let _ = writeln!(peer_stream, "{e}");
+                        }
+                    }
+                }
+
+                // the jitdump= is the switch above.
+                if cmd.trim() == "jit" {
+                    let mut out = String::new();
+                    get_vm().profiler().dump_jit(&mut out);
+                    let _ = peer_stream.write_all(out.as_bytes());
+                }
+
+                if cmd.starts_with("gc") {
+                    let mut out = String::new();
+                    get_vm_mut().profiler().gc_timeline().dump(&mut out);
+                    let _ = peer_stream.write_all(out.as_bytes());
+                }
+
                 if cmd.starts_with("quit") {
                     break;
                 }
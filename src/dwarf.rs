@@ -1,5 +1,5 @@
 #![allow(unused)]
-use std::{ops::Add, ptr};
+use std::ptr;
 const DW_STACK_SLOT: i32 = std::mem::size_of::<*const ()>() as _;
 
 #[cfg(any(target_arch = "x86_64", target_arch = "i386"))]
@@ -12,11 +12,11 @@ pub const DWARF_SUPPORTED: bool = false;
 mod target64 {
     pub const DW_REG_FP: i32 = 6;
     pub const DW_REG_SP: i32 = 7;
-    pub const DW_REG_PC: i32 = 8;
+    pub const DW_REG_PC: i32 = 16;
 }
 
 #[cfg(target_pointer_width = "64")]
-use target64::*;
+pub use target64::*;
 
 #[cfg(target_pointer_width = "32")]
 mod target32 {
@@ -26,49 +26,102 @@ mod target32 {
 }
 
 #[cfg(target_pointer_width = "32")]
-use target32::*;
+pub use target32::*;
+
+use crate::log_warn;
+
+/// the special rule of the PLT entries.
+pub const DW_REG_PLT: i32 = 128;
+/// the cfa rule is not supported, like the cfa expression.
+pub const DW_REG_INVALID: i32 = 255;
+/// the fp is not saved in the frame.
+pub const DW_SAME_FP: i32 = 0x80000000u32 as i32;
 
-use crate::log_error;
+const DW_CFA_NOP: u8 = 0x0;
+const DW_CFA_SET_LOC: u8 = 0x1;
+const DW_CFA_ADVANCE_LOC1: u8 = 0x2;
+const DW_CFA_ADVANCE_LOC2: u8 = 0x3;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x4;
+const DW_CFA_OFFSET_EXTENDED: u8 = 0x5;
+const DW_CFA_RESTORE_EXTENDED: u8 = 0x6;
+const DW_CFA_UNDEFINED: u8 = 0x7;
+const DW_CFA_SAME_VALUE: u8 = 0x8;
+const DW_CFA_REGISTER: u8 = 0x9;
+const DW_CFA_REMEMBER_STATE: u8 = 0xa;
+const DW_CFA_RESTORE_STATE: u8 = 0xb;
+const DW_CFA_DEF_CFA: u8 = 0xc;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0xd;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0xe;
+const DW_CFA_DEF_CFA_EXPRESSION: u8 = 0xf;
+const DW_CFA_EXPRESSION: u8 = 0x10;
+const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+const DW_CFA_DEF_CFA_SF: u8 = 0x12;
+const DW_CFA_DEF_CFA_OFFSET_SF: u8 = 0x13;
+const DW_CFA_VAL_OFFSET: u8 = 0x14;
+const DW_CFA_VAL_OFFSET_SF: u8 = 0x15;
+const DW_CFA_VAL_EXPRESSION: u8 = 0x16;
+const DW_CFA_GNU_ARGS_SIZE: u8 = 0x2e;
 
-static DEFAULT_FRAME: FrameDesc = FrameDesc {
+// the primary opcodes in the high 2 bits.
+const DW_CFA_ADVANCE_LOC: u8 = 0x1;
+const DW_CFA_OFFSET: u8 = 0x2;
+const DW_CFA_RESTORE: u8 = 0x3;
+
+/// the frame with the rbp based frame, used when there is no dwarf info.
+pub static DEFAULT_FRAME: FrameDesc = FrameDesc {
     loc: 0,
     cfa: DW_REG_FP | (2 * DW_STACK_SLOT) << 8,
-    fp_off: 0,
+    fp_off: -2 * DW_STACK_SLOT,
 };
 
+/// the unwind rule from the `loc` to the next row.
+/// the low 8 bits of the `cfa` is the register, the high bits is the offset.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct FrameDesc {
     pub loc: u32,
     pub cfa: i32,
     pub fp_off: i32,
 }
 
-struct DwarfParser {
+impl FrameDesc {
+    #[inline(always)]
+    pub fn cfa_reg(&self) -> i32 {
+        self.cfa & 0xff
+    }
+
+    #[inline(always)]
+    pub fn cfa_off(&self) -> i32 {
+        self.cfa >> 8
+    }
+}
+
+pub struct DwarfParser {
     ptr: *const i8,
-    name: *const i8,
+    name: String,
     image_base: *const i8,
     code_align: u32,
     data_align: i32,
     table: Vec<FrameDesc>,
-    prev_idx: isize,
 }
 
 impl DwarfParser {
-    pub unsafe fn new(name: *const i8, image_base: *const i8, eh_frame_hdr: *const i8) -> Self {
+    pub unsafe fn new(name: &str, image_base: *const i8, eh_frame_hdr: *const i8) -> Self {
         let mut parser = Self {
-            name,
+            name: name.to_string(),
             image_base,
-            code_align: 0,
-            data_align: 0,
-            table: Vec::new(),
-            prev_idx: -1,
+            code_align: 1,
+            data_align: -DW_STACK_SLOT,
+            table: Vec::with_capacity(128),
             ptr: ptr::null(),
         };
         parser.parse(eh_frame_hdr);
         parser
     }
 
-    fn prev(&self) -> Option<&FrameDesc> {
-        self.table.get(self.prev_idx as usize)
+    /// the frame table sorted by the loc.
+    pub fn into_table(mut self) -> Vec<FrameDesc> {
+        self.table.sort_by_key(|f| f.loc);
+        self.table
     }
 
     /// parse the .eh_frame_hdr section
@@ -92,13 +145,15 @@ impl DwarfParser {
             || (fde_count_enc & 0x7) != 0x3
             || (table_enc & 0xf7) != 0x33
         {
-            log_error!("WARN: .eh_frame_hdr {version:#X} {eh_frame_ptr_enc:#X} {fde_count_enc:#X} {table_enc:#X}");
+            log_warn!("WARN: .eh_frame_hdr {version:#X} {eh_frame_ptr_enc:#X} {fde_count_enc:#X} {table_enc:#X}");
             return;
         }
-        let fde_count = *(eh_frame_hdr.add(8) as *const u32);
-        let table = *(eh_frame_hdr.add(16) as *const u32);
+        let fde_count = ptr::read_unaligned(eh_frame_hdr.add(8) as *const u32) as usize;
+        // the table entries are pairs of (initial location, fde address) relative to the eh_frame_hdr.
+        let table = eh_frame_hdr.add(12) as *const i32;
         for i in 0..fde_count {
-            self.ptr = eh_frame_hdr.add(table.add(i * 2) as _);
+            let fde = ptr::read_unaligned(table.add(i * 2 + 1));
+            self.ptr = eh_frame_hdr.offset(fde as _);
             self.parse_fde();
         }
     }
@@ -112,12 +167,12 @@ impl DwarfParser {
 
     #[inline(always)]
     unsafe fn getu32(&mut self) -> u32 {
-        *self.add(4)
+        ptr::read_unaligned(self.add(4))
     }
 
     #[inline(always)]
     unsafe fn getu16(&mut self) -> u16 {
-        *self.add(2)
+        ptr::read_unaligned(self.add(2))
     }
 
     #[inline(always)]
@@ -125,54 +180,51 @@ impl DwarfParser {
         *self.add(1)
     }
 
+    /// read the pc relative pointer.
     #[inline(always)]
     unsafe fn get_ptr(&mut self) -> *const i8 {
         let old_ptr = self.ptr;
-        old_ptr.add(*self.add(4))
+        let off = ptr::read_unaligned(self.add::<i32>(4));
+        old_ptr.offset(off as _)
     }
 
     unsafe fn get_leb(&mut self) -> u32 {
         let mut result = 0u32;
-        let mut shift = 7;
+        let mut shift = 0;
         loop {
-            let p = *self.ptr as u8;
-            result |= (p as u32 & 0x7f) << shift;
+            let p = self.getu8();
+            if shift < 32 {
+                result |= (p as u32 & 0x7f) << shift;
+            }
             if p & 0x80 == 0 {
                 break;
             }
             shift += 7;
-            self.ptr = self.ptr.add(1);
         }
         result
     }
 
     unsafe fn get_sleb(&mut self) -> i32 {
         let mut result = 0i32;
-        let mut shift = 7;
+        let mut shift = 0;
         loop {
-            let p = *self.ptr as u8;
-            result |= (p as i32 & 0x7f) << shift;
+            let p = self.getu8();
+            if shift < 32 {
+                result |= (p as i32 & 0x7f) << shift;
+            }
+            shift += 7;
             if p & 0x80 == 0 {
-                if (p & 0x40) != 0 {
-                    shift += 7;
-                    if shift < 32 {
-                        result |= -1 << shift;
-                    }
+                if (p & 0x40) != 0 && shift < 32 {
+                    result |= -1 << shift;
                 }
                 break;
             }
-            shift += 7;
-            self.ptr = self.ptr.add(1);
         }
         result
     }
 
     unsafe fn skip_leb(&mut self) {
-        let mut b = *self.ptr as u8;
-        while b & 0x80 != 0 {
-            self.ptr = self.ptr.add(1);
-            b = *self.ptr as u8;
-        }
+        while self.getu8() & 0x80 != 0 {}
     }
 
     unsafe fn parse_cie(&mut self) {
@@ -181,13 +233,10 @@ impl DwarfParser {
             return;
         }
         let cie_start = self.ptr;
+        // skip the cie id and the version
         self.ptr = self.ptr.add(5);
-        loop {
-            if *self.ptr == 0 {
-                break;
-            }
-            self.ptr = self.ptr.add(1);
-        }
+        // skip the augmentation string
+        while self.getu8() != 0 {}
         self.code_align = self.get_leb();
         self.data_align = self.get_sleb();
         self.ptr = cie_start.add(cie_len as _);
@@ -200,12 +249,131 @@ impl DwarfParser {
         }
         let fde_start = self.ptr;
         let cie_off = self.getu32();
-        self.ptr = fde_start.add(cie_off as _);
+        self.ptr = fde_start.sub(cie_off as _);
         self.parse_cie();
         self.ptr = fde_start.add(4);
-        let range_start = self.get_ptr().offset_from(self.image_base);
+        let range_start = self.get_ptr().offset_from(self.image_base) as u32;
         let range_len = self.getu32();
-        self.ptr = self.ptr.add(self.get_leb() as _);
-        todo!()
+        // skip the augmentation data
+        let aug_len = self.get_leb();
+        self.ptr = self.ptr.add(aug_len as _);
+        self.parse_instructions(range_start, fde_start.add(fde_len as _));
+        self.add_record(range_start.wrapping_add(range_len), DW_REG_SP, DW_STACK_SLOT, DW_SAME_FP);
+    }
+
+    /// run the call frame instructions, only the cfa and the fp rule are tracked.
+    unsafe fn parse_instructions(&mut self, mut loc: u32, end: *const i8) {
+        let code_align = self.code_align;
+        let data_align = self.data_align;
+        let mut cfa_reg = DW_REG_SP;
+        let mut cfa_off = DW_STACK_SLOT;
+        let mut fp_off = DW_SAME_FP;
+        let mut remembered = (cfa_reg, cfa_off, fp_off);
+        while self.ptr < end {
+            let op = self.getu8();
+            match op >> 6 {
+                0 => match op {
+                    DW_CFA_NOP | DW_CFA_SET_LOC => self.ptr = end,
+                    DW_CFA_ADVANCE_LOC1 => {
+                        self.add_record(loc, cfa_reg, cfa_off, fp_off);
+                        loc += self.getu8() as u32 * code_align;
+                    }
+                    DW_CFA_ADVANCE_LOC2 => {
+                        self.add_record(loc, cfa_reg, cfa_off, fp_off);
+                        loc += self.getu16() as u32 * code_align;
+                    }
+                    DW_CFA_ADVANCE_LOC4 => {
+                        self.add_record(loc, cfa_reg, cfa_off, fp_off);
+                        loc += self.getu32() * code_align;
+                    }
+                    DW_CFA_OFFSET_EXTENDED => {
+                        if self.get_leb() as i32 == DW_REG_FP {
+                            fp_off = self.get_leb() as i32 * data_align;
+                        } else {
+                            self.skip_leb();
+                        }
+                    }
+                    DW_CFA_RESTORE_EXTENDED | DW_CFA_UNDEFINED | DW_CFA_SAME_VALUE => self.skip_leb(),
+                    DW_CFA_REGISTER => {
+                        self.skip_leb();
+                        self.skip_leb();
+                    }
+                    DW_CFA_REMEMBER_STATE => remembered = (cfa_reg, cfa_off, fp_off),
+                    DW_CFA_RESTORE_STATE => (cfa_reg, cfa_off, fp_off) = remembered,
+                    DW_CFA_DEF_CFA => {
+                        cfa_reg = self.get_leb() as i32;
+                        cfa_off = self.get_leb() as i32;
+                    }
+                    DW_CFA_DEF_CFA_REGISTER => cfa_reg = self.get_leb() as i32,
+                    DW_CFA_DEF_CFA_OFFSET => cfa_off = self.get_leb() as i32,
+                    DW_CFA_DEF_CFA_EXPRESSION => {
+                        // the 11 bytes expression is the PLT entry rule.
+                        let len = self.get_leb();
+                        cfa_reg = if len == 11 { DW_REG_PLT } else { DW_REG_INVALID };
+                        cfa_off = DW_STACK_SLOT;
+                        self.ptr = self.ptr.add(len as _);
+                    }
+                    DW_CFA_EXPRESSION | DW_CFA_VAL_EXPRESSION => {
+                        self.skip_leb();
+                        let len = self.get_leb();
+                        self.ptr = self.ptr.add(len as _);
+                    }
+                    DW_CFA_OFFSET_EXTENDED_SF => {
+                        if self.get_leb() as i32 == DW_REG_FP {
+                            fp_off = self.get_sleb() * data_align;
+                        } else {
+                            self.skip_leb();
+                        }
+                    }
+                    DW_CFA_DEF_CFA_SF => {
+                        cfa_reg = self.get_leb() as i32;
+                        cfa_off = self.get_sleb() * data_align;
+                    }
+                    DW_CFA_DEF_CFA_OFFSET_SF => cfa_off = self.get_sleb() * data_align,
+                    DW_CFA_VAL_OFFSET | DW_CFA_VAL_OFFSET_SF => {
+                        self.skip_leb();
+                        self.skip_leb();
+                    }
+                    DW_CFA_GNU_ARGS_SIZE => self.skip_leb(),
+                    _ => {
+                        log_warn!("WARN: unknown dwarf instruction {op:#x} in {}", self.name);
+                        return;
+                    }
+                },
+                DW_CFA_ADVANCE_LOC => {
+                    self.add_record(loc, cfa_reg, cfa_off, fp_off);
+                    loc += (op & 0x3f) as u32 * code_align;
+                }
+                DW_CFA_OFFSET => {
+                    if (op & 0x3f) as i32 == DW_REG_FP {
+                        fp_off = self.get_leb() as i32 * data_align;
+                    } else {
+                        self.skip_leb();
+                    }
+                }
+                DW_CFA_RESTORE => {
+                    if (op & 0x3f) as i32 == DW_REG_FP {
+                        fp_off = DW_SAME_FP;
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        self.add_record(loc, cfa_reg, cfa_off, fp_off);
+    }
+
+    /// the row at the same loc replaces the previous one, the row with the same rule is merged.
+    fn add_record(&mut self, loc: u32, cfa_reg: i32, cfa_off: i32, fp_off: i32) {
+        let cfa = cfa_reg | cfa_off << 8;
+        if let Some(prev) = self.table.last_mut() {
+            if prev.loc == loc {
+                *prev = FrameDesc { loc, cfa, fp_off };
+                return;
+            }
+            if prev.cfa == cfa && prev.fp_off == fp_off {
+                return;
+            }
+        }
+        self.table.push(FrameDesc { loc, cfa, fp_off });
     }
 }
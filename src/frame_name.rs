@@ -1,95 +1,227 @@
-use std::{sync::Mutex, collections::HashMap, ptr};
-use std::ffi::CStr;
+use std::{sync::Mutex, collections::HashMap, ffi::CStr};
 
-use cpp_demangle::Symbol;
+use cpp_demangle::{Symbol, DemangleOptions};
 
 use crate::{
     profiler::ThreadInfo, 
     vm::{
-        JVMPICallFrame, BCI_THREADID, BCI_NATIVE_FRAME
+        JVMPICallFrame, BCI_THREADID, BCI_NATIVE_FRAME, BCI_GC, BCI_ERROR, BCI_CODE_BLOB, BCI_NATIVE_PC, asgct_failure_name
     }, 
     code_cache::CodeBlob, 
-    jvmti_native::{jmethodID, jclass}, 
-    get_vm, cstr_2_str
+    frame_type::FrameType,
+    method_dict::MethodDict,
+    native_line::{NativeLine, NativeLines},
+    jvmti_native::jmethodID, 
+    get_vm
 };
 
+/// the detail of the java frame name.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameDetail {
+    /// Class.method(sig)
+    Method,
+    /// Class.method(sig)[Source.java:line], the native frames get file:line from the debug info.
+    Line,
+    /// Class.method(sig)[bci:n]
+    Bci,
+}
+
+impl FrameDetail {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "method" => Some(Self::Method),
+            "line" => Some(Self::Line),
+            "bci" => Some(Self::Bci),
+            _ => None,
+        }
+    }
+}
+
+/// how the native symbols are demangled.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Demangle {
+    /// strip the `::h<hash>` suffix of the rust symbols.
+    pub strip_hash: bool,
+    /// keep the parameter list of the C++ symbols.
+    pub cpp_params: bool,
+}
+
+impl Default for Demangle {
+    fn default() -> Self {
+        Self {
+            strip_hash: true,
+            cpp_params: true,
+        }
+    }
+}
+
+impl Demangle {
+    /// parse the comma separated flags like `nohash,noparams`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut demangle = Self::default();
+        for flag in s.trim().split(',') {
+            match flag.trim() {
+                "hash" => demangle.strip_hash = false,
+                "nohash" => demangle.strip_hash = true,
+                "params" => demangle.cpp_params = true,
+                "noparams" => demangle.cpp_params = false,
+                _ => return None,
+            }
+        }
+        Some(demangle)
+    }
+
+    /// demangle the rust(legacy and v0) and C++ symbol, the unknown symbol is kept raw.
+    pub fn decode(&self, name: &[u8], out: &mut Vec<u8>) {
+        use std::io::Write;
+        let is_mangled = name.starts_with(b"_Z") || name.starts_with(b"_R");
+        if !is_mangled {
+            out.extend_from_slice(name);
+            return;
+        }
+        // the rust legacy symbol is also a valid C++ symbol, so try the rust first.
+        if let Ok(symbol) = std::str::from_utf8(name) {
+            if let Ok(demangled) = rustc_demangle::try_demangle(symbol) {
+                let _ = if self.strip_hash {
+                    write!(out, "{demangled:#}")
+                } else {
+                    write!(out, "{demangled}")
+                };
+                return;
+            }
+        }
+        let demangled = Symbol::new(name).ok().and_then(|symbol| {
+            let options = if self.cpp_params {
+                DemangleOptions::new()
+            } else {
+                DemangleOptions::new().no_params()
+            };
+            symbol.demangle(&options).ok()
+        });
+        match demangled {
+            Some(demangled) => out.extend_from_slice(demangled.as_bytes()),
+            None => out.extend_from_slice(name),
+        }
+    }
+}
+
 pub struct FrameName<'a> {
     threads_pool: &'a Mutex<HashMap<u64, ThreadInfo>>,
+    method_dict: &'a Mutex<MethodDict>,
+    detail: FrameDetail,
+    demangle: Demangle,
+    native_lines: NativeLines,
+    lines: Vec<NativeLine>,
     name: Vec<u8>
 }
 
 impl<'a> FrameName<'a> {
-    pub fn new(threads_pool: &'a Mutex<HashMap<u64, ThreadInfo>>) -> Self {
+    pub fn new(
+        threads_pool: &'a Mutex<HashMap<u64, ThreadInfo>>,
+        method_dict: &'a Mutex<MethodDict>,
+        detail: FrameDetail,
+        demangle: Demangle,
+    ) -> Self {
         Self {
             threads_pool,
+            method_dict,
+            detail,
+            demangle,
+            native_lines: NativeLines::new(),
+            lines: Vec::new(),
             name: Vec::new(),
         }
     }
 
-    unsafe fn java_method_name(&mut self, method_id: jmethodID) -> Option<()> {
-        let jvmti = get_vm().jvmti();
-        let mut method_name_ptr = ptr::null_mut();
-        let mut method_sig_ptr = ptr::null_mut();
-        let mut class_sig_ptr = ptr::null_mut();
-        let mut class: jclass = ptr::null_mut();
-        if 0 == jvmti.get_method_name(method_id, &mut method_name_ptr, &mut method_sig_ptr, ptr::null_mut())? {
-            if 0 == jvmti.get_method_declaring_class(method_id, &mut class)? {
-                if 0 == jvmti.get_class_signature(class, &mut class_sig_ptr, ptr::null_mut())? {
-                    let class_sig = cstr_2_str!(class_sig_ptr);
-                    let method_name = cstr_2_str!(method_name_ptr);
-                    //trim the class Ljava/lang/String; 
-                    self.java_class_name(&class_sig[1..class_sig.len() - 1].as_bytes());
-                    self.name.push(b'.');
-                    self.name.extend_from_slice(&method_name.as_bytes());
-                    let method_sig = cstr_2_str!(method_sig_ptr);
-                    self.name.extend_from_slice(method_sig.as_bytes());
+    /// the method is interned on the ClassPrepare or when the trace is drained,
+    /// jvmti is only called for the method missed by both.
+    fn java_method_name(&mut self, method_id: jmethodID, bci: i32) -> Option<()> {
+        let mut dict = self.method_dict.lock().ok()?;
+        let idx = dict.intern(get_vm().jvmti(), method_id)?;
+        let method = dict.get(idx)?;
+        self.name.truncate(0);
+        self.name.extend_from_slice(method.class.as_bytes());
+        self.name.push(b'.');
+        self.name.extend_from_slice(method.name.as_bytes());
+        self.name.extend_from_slice(method.sig.as_bytes());
+        match self.detail {
+            FrameDetail::Method => {}
+            FrameDetail::Line => {
+                if let Some(line) = method.line_of(bci) {
+                    let source_file = method.source_file.as_deref().unwrap_or("Unknown");
+                    self.name.extend_from_slice(format!("[{source_file}:{line}]").as_bytes());
+                }
+            }
+            FrameDetail::Bci => {
+                if bci >= 0 {
+                    self.name.extend_from_slice(format!("[bci:{bci}]").as_bytes());
                 }
             }
         }
-        jvmti.deallocate(method_name_ptr as _);
-        jvmti.deallocate(method_sig_ptr as _);
-        jvmti.deallocate(class_sig_ptr as _);
         Some(())
     }
 
-    fn java_class_name(&mut self, class: &[u8]) {
-        let mut array_dimension = 0;
-        while class[array_dimension] == b'[' {
-            array_dimension += 1;
-        }
-        if array_dimension == 0 {
-            self.name.extend_from_slice(class);
-        } else {
-            match class[array_dimension] {
-                b'B' => self.name.extend_from_slice(b"byte"),
-                b'C' => self.name.extend_from_slice(b"char"),
-                b'I' => self.name.extend_from_slice(b"int"),
-                b'J' => self.name.extend_from_slice(b"long"),
-                b'S' => self.name.extend_from_slice(b"short"),
-                b'Z' => self.name.extend_from_slice(b"boolean"),
-                b'F' => self.name.extend_from_slice(b"short"),
-                b'D' => self.name.extend_from_slice(b"double"),
-                _ => self.name.extend_from_slice(&class[1..class.len() - array_dimension - 2]),
+    /// the inlined functions of the pc are splited by ';' like the frames with the line detail,
+    /// the outermost first.
+    fn native_pc_name(&mut self, pc: *const i8) {
+        let profiler = get_vm().profiler();
+        let symbol = match profiler.find_native_method(pc) {
+            Some(blob) => blob.name_str(),
+            None => {
+                self.name.extend_from_slice(b"[unknown]");
+                return;
             }
+        };
+        let resolved = self.detail == FrameDetail::Line && profiler
+            .find_library_by_address(pc)
+            .is_some_and(|cc| self.native_lines.resolve(cc, pc, &mut self.lines));
+        if !resolved {
+            self.demangle.decode(symbol.as_bytes(), &mut self.name);
+            return;
         }
-        for _ in 0..array_dimension {
-            self.name.extend_from_slice(b"[]")
-        }
-        let name = &mut self.name;
-        //replace the / to '.' like java/lang/String
-        for i in 0..name.len() {
-            if name[i] == b'/' && !name[i+1].is_ascii_digit() {
-                name[i] = b'.';
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                self.name.push(b';');
+            }
+            let function = match line.function.as_deref() {
+                Some(function) => function,
+                None if i == 0 => symbol,
+                None => "[inlined]",
+            };
+            self.demangle.decode(function.as_bytes(), &mut self.name);
+            if let (Some(file), Some(line)) = (line.file.as_deref(), line.line) {
+                let file = file.rsplit('/').next().unwrap_or(file);
+                self.name.extend_from_slice(format!("[{file}:{line}]").as_bytes());
             }
         }
     }
 
-    fn decode_native_name(&mut self, name: &[u8]) {
-        if let Ok(symbol) = Symbol::new(name) {
-            let symbol_str = symbol.to_string();
-            self.name.extend_from_slice(symbol_str.as_bytes());
-        } else {
-            self.name.extend_from_slice(name);
+    /// the source file and the line of the frame, the java file is under the package path.
+    /// the native pc is located in the outermost function of the inlined chain.
+    pub fn location(&mut self, frame: &JVMPICallFrame) -> Option<(String, u32)> {
+        match frame.bci {
+            BCI_NATIVE_PC => {
+                let pc = frame.method_id as *const i8;
+                let cc = get_vm().profiler().find_library_by_address(pc)?;
+                if !self.native_lines.resolve(cc, pc, &mut self.lines) {
+                    return None;
+                }
+                let outermost = self.lines.first()?;
+                Some((outermost.file.clone()?, outermost.line?))
+            }
+            bci if frame.is_java() => {
+                let (_, bci) = FrameType::decode(bci);
+                let mut dict = self.method_dict.lock().ok()?;
+                let idx = dict.intern(get_vm().jvmti(), frame.method_id)?;
+                let method = dict.get(idx)?;
+                let source_file = method.source_file.as_deref()?;
+                let file = match method.class.rsplit_once('.') {
+                    Some((package, _)) => format!("{}/{source_file}", package.replace('.', "/")),
+                    None => source_file.to_string(),
+                };
+                Some((file, method.line_of(bci)?.max(0) as u32))
+            }
+            _ => None,
         }
     }
 
@@ -106,25 +238,61 @@ impl<'a> FrameName<'a> {
                 });
                 
             }
+            BCI_GC => self.name.extend_from_slice(b"[gc_pause]"),
+            BCI_CODE_BLOB => {
+                let name = unsafe { CStr::from_ptr(frame.method_id as *const i8) };
+                self.name.extend_from_slice(name.to_bytes());
+            }
+            BCI_ERROR => {
+                let code = frame.method_id as isize as i32;
+                self.name.extend_from_slice(asgct_failure_name(code).as_bytes());
+            }
+            BCI_NATIVE_PC => self.native_pc_name(frame.method_id as *const i8),
             BCI_NATIVE_FRAME => {
                 let code_blob: &CodeBlob = unsafe {&*(frame.method_id as *const CodeBlob)};
-                let mname = code_blob.name_str().as_bytes();
-                if mname[0] == b'_' && mname[1] == b'Z' {
-                    self.decode_native_name(mname);
-                } else {
-                    self.name.extend_from_slice(mname);
-                }
+                self.demangle.decode(code_blob.name_str().as_bytes(), &mut self.name);
             }
             _ => {
-                unsafe {
-                    if let None = self.java_method_name(frame.method_id) {
-                        self.name.extend_from_slice(b"[jvmtiError]");
-                    }
-                };
+                let (frame_type, bci) = FrameType::decode(frame.bci);
+                if let None = self.java_method_name(frame.method_id, bci) {
+                    self.name.truncate(0);
+                    self.name.extend_from_slice(b"[jvmtiError]");
+                }
+                self.name.extend_from_slice(frame_type.suffix().as_bytes());
             }
         };
         unsafe {
             std::str::from_utf8_unchecked(&self.name)   
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_detail() {
+        assert_eq!(FrameDetail::parse("line"), Some(FrameDetail::Line));
+        assert_eq!(FrameDetail::parse("all"), None);
+    }
+
+    #[test]
+    fn test_demangle() {
+        let decode = |demangle: Demangle, name: &str| {
+            let mut out = Vec::new();
+            demangle.decode(name.as_bytes(), &mut out);
+            String::from_utf8(out).unwrap()
+        };
+        let default = Demangle::default();
+        let raw = Demangle::parse("hash,noparams").unwrap();
+        let legacy = "_ZN4core3fmt5write17h0123456789abcdefE";
+        assert_eq!(decode(default, legacy), "core::fmt::write");
+        assert_eq!(decode(raw, legacy), "core::fmt::write::h0123456789abcdef");
+        assert_eq!(decode(default, "_RNvNtCs1234_7mycrate3foo3bar"), "mycrate::foo::bar");
+        assert_eq!(decode(default, "_ZN3foo3barEi"), "foo::bar(int)");
+        assert_eq!(decode(raw, "_ZN3foo3barEi"), "foo::bar");
+        assert_eq!(decode(default, "malloc"), "malloc");
+        assert_eq!(Demangle::parse("nohash,all"), None);
+    }
+}
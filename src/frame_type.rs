@@ -0,0 +1,141 @@
+use crate::vm_struct::NMethod;
+use crate::vm::JVMPICallFrame;
+
+/// the frame type is kept in the high bits of the bci of the java frame.
+const FRAME_TYPE_SHIFT: i32 = 24;
+const FRAME_BCI_MASK: i32 = (1 << FRAME_TYPE_SHIFT) - 1;
+/// the bci -1 of the typed frame, the bci isn't known so no line is printed.
+const UNKNOWN_BCI: i32 = -1;
+
+const TYPE_INTERPRETED: i32 = 1;
+const TYPE_INLINED: i32 = 2;
+/// the compiled frame type is TYPE_COMPILED + level.
+const TYPE_COMPILED: i32 = 3;
+const MAX_COMPILE_LEVEL: i32 = 4;
+
+/// how the java frame executed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameType {
+    Unknown,
+    Interpreted,
+    Inlined,
+    /// compiled at the tier, 1-3 is C1, 4 is C2.
+    Compiled(i32),
+}
+
+impl FrameType {
+    /// encode the type into the bci, the negative bci isn't a real bci and keep it,
+    /// except the unknown bci -1 of the typed frame.
+    pub fn encode(self, bci: i32) -> i32 {
+        let typ = match self {
+            Self::Unknown => return bci,
+            Self::Interpreted => TYPE_INTERPRETED,
+            Self::Inlined => TYPE_INLINED,
+            Self::Compiled(level) => TYPE_COMPILED + level.clamp(0, MAX_COMPILE_LEVEL),
+        };
+        if bci == UNKNOWN_BCI {
+            return typ << FRAME_TYPE_SHIFT | FRAME_BCI_MASK;
+        }
+        if !(0..FRAME_BCI_MASK).contains(&bci) {
+            return bci;
+        }
+        typ << FRAME_TYPE_SHIFT | bci
+    }
+
+    /// decode the encoded bci, return the frame type and the original bci.
+    pub fn decode(bci: i32) -> (Self, i32) {
+        if bci <= FRAME_BCI_MASK {
+            return (Self::Unknown, bci);
+        }
+        let typ = match bci >> FRAME_TYPE_SHIFT {
+            TYPE_INTERPRETED => Self::Interpreted,
+            TYPE_INLINED => Self::Inlined,
+            n => Self::Compiled(n - TYPE_COMPILED),
+        };
+        match bci & FRAME_BCI_MASK {
+            FRAME_BCI_MASK => (typ, UNKNOWN_BCI),
+            bci => (typ, bci),
+        }
+    }
+
+    /// the suffix of the frame name in the outputs.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Self::Unknown => "",
+            Self::Interpreted => "_[0]",
+            Self::Inlined => "_[i]",
+            Self::Compiled(1) => "_[1]",
+            Self::Compiled(2) => "_[2]",
+            Self::Compiled(3) => "_[3]",
+            Self::Compiled(_) => "_[j]",
+        }
+    }
+
+    /// the name read back from the outputs without the frame type suffix.
+    pub fn strip_suffix(name: &str) -> &str {
+        [Self::Interpreted, Self::Inlined, Self::Compiled(1), Self::Compiled(2), Self::Compiled(3), Self::Compiled(4)]
+            .iter()
+            .find_map(|typ| name.strip_suffix(typ.suffix()))
+            .unwrap_or(name)
+    }
+
+    /// classify the java frames by the nmethod of the top java pc, return the number of the
+    /// frames classified, the callers are classified by the jit code map.
+    /// the frame of the nmethod's method is compiled and the frames above it are inlined,
+    /// if the pc is in the interpreter, the first java frame is interpreted.
+    pub unsafe fn fill_frame_types(frames: &mut [JVMPICallFrame], nmethod: &NMethod) -> usize {
+        if nmethod.is_nmethod() {
+            let method_id = match nmethod.method().and_then(|m| m.id()) {
+                Some(id) if !id.is_null() => id,
+                _ => return 0,
+            };
+            let level = nmethod.level();
+            for i in 0..frames.len() {
+                if !frames[i].is_java() {
+                    break;
+                }
+                if frames[i].method_id == method_id {
+                    frames[i].bci = Self::Compiled(level).encode(frames[i].bci);
+                    for frame in frames[0..i].iter_mut() {
+                        frame.bci = Self::Inlined.encode(frame.bci);
+                    }
+                    return i + 1;
+                }
+            }
+        } else if nmethod.is_interpreter() {
+            if let Some(i) = frames.iter().position(|f| f.is_java()) {
+                frames[i].bci = Self::Interpreted.encode(frames[i].bci);
+                return i + 1;
+            }
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::BCI_NATIVE_FRAME;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(FrameType::Unknown.encode(12), 12);
+        assert_eq!(FrameType::decode(12), (FrameType::Unknown, 12));
+        let bci = FrameType::Interpreted.encode(7);
+        assert_eq!(FrameType::decode(bci), (FrameType::Interpreted, 7));
+        let bci = FrameType::Compiled(4).encode(0);
+        assert_eq!(FrameType::decode(bci), (FrameType::Compiled(4), 0));
+        assert_eq!(FrameType::decode(bci).0.suffix(), "_[j]");
+        assert_eq!(FrameType::strip_suffix("Foo.bar()V_[j]"), "Foo.bar()V");
+        assert_eq!(FrameType::strip_suffix("Foo.bar()V_[2]"), "Foo.bar()V");
+        assert_eq!(FrameType::strip_suffix("libc.so_[k]"), "libc.so_[k]");
+        let bci = FrameType::Inlined.encode(100);
+        assert_eq!(FrameType::decode(bci), (FrameType::Inlined, 100));
+        // the special bci keep untouched.
+        assert_eq!(FrameType::Inlined.encode(-3), -3);
+        // the unknown bci keeps the type.
+        let bci = FrameType::Interpreted.encode(-1);
+        assert_eq!(FrameType::decode(bci), (FrameType::Interpreted, -1));
+        assert_eq!(FrameType::decode(BCI_NATIVE_FRAME), (FrameType::Unknown, BCI_NATIVE_FRAME));
+    }
+}
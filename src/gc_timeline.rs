@@ -0,0 +1,297 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::os::OS;
+
+/// the width of one timeline bucket, 100ms.
+const BUCKET_NANOS: u64 = 100_000_000;
+/// 10 minutes of 100ms buckets, the bucket pairs are merged when the elapsed time is past them.
+const MAX_BUCKETS: usize = 6000;
+const MAX_BAR_WIDTH: u32 = 60;
+/// the name prefixes of the hotspot GC and VM threads, the name is truncated to 15 bytes.
+const GC_THREAD_PREFIXES: [&[u8]; 9] = [
+    b"VM Thread",
+    b"GC Thread",
+    b"GC task thread",
+    b"Gang worker",
+    b"G1 ",
+    b"Concurrent Mark",
+    b"ZDriver",
+    b"ZWorker",
+    b"Shenandoah",
+];
+
+#[derive(Clone)]
+pub struct GcPause {
+    pub id: u32,
+    /// nanoseconds since the timeline start.
+    pub start: u64,
+    pub duration: u64,
+    /// the samples attributed to the pause.
+    pub samples: u32,
+}
+
+/// Record the GC pauses reported by GarbageCollectionStart/GarbageCollectionFinish
+/// and the sample count of every bucket, so the pauses can be laid over the samples.
+/// The signal handler only touches the atomics.
+pub struct GcTimeline {
+    start_time: AtomicU64,
+    /// start time of the running GC, 0 when there is no GC.
+    active_start: AtomicU64,
+    active_samples: AtomicU32,
+    gc_count: AtomicU32,
+    /// the bucket width is BUCKET_NANOS << bucket_shift.
+    bucket_shift: AtomicU32,
+    buckets: Vec<AtomicU32>,
+    pauses: Mutex<Vec<GcPause>>,
+}
+
+impl GcTimeline {
+    pub fn new() -> Self {
+        Self {
+            start_time: AtomicU64::new(OS::nano_time()),
+            active_start: AtomicU64::new(0),
+            active_samples: AtomicU32::new(0),
+            gc_count: AtomicU32::new(0),
+            bucket_shift: AtomicU32::new(0),
+            buckets: (0..MAX_BUCKETS).map(|_| AtomicU32::new(0)).collect(),
+            pauses: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// clear the recorded data, the timeline start from now.
+    pub fn reset(&self) {
+        self.buckets
+            .iter()
+            .for_each(|b| b.store(0, Ordering::Relaxed));
+        self.bucket_shift.store(0, Ordering::Relaxed);
+        if let Ok(mut pauses) = self.pauses.lock() {
+            pauses.clear();
+        }
+        self.active_start.store(0, Ordering::Release);
+        self.active_samples.store(0, Ordering::Relaxed);
+        self.gc_count.store(0, Ordering::Relaxed);
+        self.start_time.store(OS::nano_time(), Ordering::Release);
+    }
+
+    /// the nano time of the timeline start, the pause start is relative to it.
+    #[inline(always)]
+    pub fn start_time(&self) -> u64 {
+        self.start_time.load(Ordering::Acquire)
+    }
+
+    #[inline(always)]
+    fn bucket_nanos(&self) -> u64 {
+        BUCKET_NANOS << self.bucket_shift.load(Ordering::Acquire)
+    }
+
+    pub fn pauses(&self) -> Vec<GcPause> {
+        match self.pauses.lock() {
+            Ok(pauses) => pauses.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn gc_active(&self) -> bool {
+        self.active_start.load(Ordering::Acquire) != 0
+    }
+
+    pub fn gc_start(&self) {
+        self.gc_count.fetch_add(1, Ordering::Relaxed);
+        self.active_samples.store(0, Ordering::Relaxed);
+        self.active_start.store(OS::nano_time(), Ordering::Release);
+    }
+
+    pub fn gc_finish(&self) {
+        let start = self.active_start.swap(0, Ordering::AcqRel);
+        if start == 0 {
+            return;
+        }
+        let now = OS::nano_time();
+        let timeline_start = self.start_time.load(Ordering::Acquire);
+        let pause = GcPause {
+            id: self.gc_count.load(Ordering::Relaxed),
+            start: start.saturating_sub(timeline_start),
+            duration: now - start,
+            samples: self.active_samples.load(Ordering::Relaxed),
+        };
+        if let Ok(mut pauses) = self.pauses.lock() {
+            pauses.push(pause);
+        }
+    }
+
+    /// count the sample into the bucket of the current time, called by the signal handler.
+    /// if the sample belongs to the running GC, return true.
+    pub fn record_sample(&self, in_gc: bool) -> bool {
+        let elapsed = OS::nano_time().saturating_sub(self.start_time.load(Ordering::Acquire));
+        loop {
+            let shift = self.bucket_shift.load(Ordering::Acquire);
+            let idx = (elapsed / (BUCKET_NANOS << shift)) as usize;
+            if idx < MAX_BUCKETS {
+                self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+                break;
+            }
+            // the winner doubles the bucket width, the others retry with the new width.
+            if self
+                .bucket_shift
+                .compare_exchange(shift, shift + 1, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                self.compact();
+            }
+        }
+        if in_gc && self.gc_active() {
+            self.active_samples.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    /// merge the bucket pairs into the first half, the bucket i/2 is drained before it's added to.
+    /// the sample counted into a drained bucket by a racing handler is kept in the later row.
+    fn compact(&self) {
+        for idx in 0..MAX_BUCKETS {
+            let samples = self.buckets[idx].swap(0, Ordering::Relaxed);
+            self.buckets[idx / 2].fetch_add(samples, Ordering::Relaxed);
+        }
+    }
+
+    /// the thread without the JNIEnv belongs to the GC if it's the GC or VM thread.
+    /// the name is read by the prctl, safe to call in the signal handler.
+    pub fn is_gc_thread(&self) -> bool {
+        let mut name = [0u8; 16];
+        let len = OS::current_thread_name(&mut name);
+        GC_THREAD_PREFIXES.iter().any(|prefix| name[..len].starts_with(prefix))
+    }
+
+    /// dump the timeline as text, every row is a bucket, the bar is the samples
+    /// and the rows covered by a pause are marked with the GC.
+    pub fn dump(&self, out: &mut String) {
+        let pauses = match self.pauses.lock() {
+            Ok(p) => p,
+            Err(_) => return,
+        };
+        let total: u64 = pauses.iter().map(|p| p.duration).sum();
+        let _ = writeln!(
+            out,
+            "GC pauses: {}, total {:.3} ms",
+            pauses.len(),
+            total as f64 / 1e6
+        );
+        for p in pauses.iter() {
+            let _ = writeln!(
+                out,
+                "  #{:<6} at {:>10.3} ms  duration {:>9.3} ms  samples {}",
+                p.id,
+                p.start as f64 / 1e6,
+                p.duration as f64 / 1e6,
+                p.samples
+            );
+        }
+        let last = match self
+            .buckets
+            .iter()
+            .rposition(|b| b.load(Ordering::Relaxed) > 0)
+        {
+            Some(last) => last,
+            None => return,
+        };
+        let max = self.buckets[..=last]
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let bucket_nanos = self.bucket_nanos();
+        let _ = writeln!(out, "timeline ({} ms per row):", bucket_nanos / 1_000_000);
+        for (idx, bucket) in self.buckets[..=last].iter().enumerate() {
+            let samples = bucket.load(Ordering::Relaxed);
+            let row_start = idx as u64 * bucket_nanos;
+            let row_end = row_start + bucket_nanos;
+            let gc_nanos: u64 = pauses
+                .iter()
+                .filter(|p| p.start < row_end && p.start + p.duration > row_start)
+                .map(|p| (p.start + p.duration).min(row_end) - p.start.max(row_start))
+                .sum();
+            let width = (samples * MAX_BAR_WIDTH / max) as usize;
+            let _ = write!(
+                out,
+                "{:>8} ms {:>6} |{:<w$}|",
+                row_start / 1_000_000,
+                samples,
+                "#".repeat(width),
+                w = MAX_BAR_WIDTH as usize
+            );
+            if gc_nanos > 0 {
+                let _ = write!(out, " GC {:.3} ms", gc_nanos as f64 / 1e6);
+            }
+            out.push('\n');
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gc_pause() {
+        let timeline = GcTimeline::new();
+        assert!(!timeline.record_sample(true));
+        timeline.gc_start();
+        assert!(timeline.gc_active());
+        assert!(timeline.record_sample(true));
+        assert!(!timeline.record_sample(false));
+        timeline.gc_finish();
+        assert!(!timeline.gc_active());
+        let pauses = timeline.pauses.lock().unwrap();
+        assert_eq!(pauses.len(), 1);
+        assert_eq!(pauses[0].id, 1);
+        assert_eq!(pauses[0].samples, 1);
+        drop(pauses);
+        let mut out = String::new();
+        timeline.dump(&mut out);
+        assert!(out.starts_with("GC pauses: 1"));
+        assert!(out.contains("| GC "));
+
+        timeline.gc_start();
+        timeline.reset();
+        assert!(!timeline.gc_active());
+        assert!(timeline.pauses().is_empty());
+        timeline.gc_start();
+        timeline.gc_finish();
+        assert_eq!(timeline.pauses()[0].id, 1);
+    }
+
+    #[test]
+    fn test_compact() {
+        let timeline = GcTimeline::new();
+        timeline.buckets[0].store(1, Ordering::Relaxed);
+        timeline.buckets[1].store(2, Ordering::Relaxed);
+        timeline.buckets[MAX_BUCKETS - 1].store(3, Ordering::Relaxed);
+        // the sample past the buckets doubles the width.
+        let start = OS::nano_time() - BUCKET_NANOS * MAX_BUCKETS as u64;
+        timeline.start_time.store(start, Ordering::Release);
+        timeline.record_sample(false);
+        assert_eq!(timeline.bucket_nanos(), BUCKET_NANOS * 2);
+        assert_eq!(timeline.buckets[0].load(Ordering::Relaxed), 3);
+        assert_eq!(timeline.buckets[MAX_BUCKETS / 2 - 1].load(Ordering::Relaxed), 3);
+        let samples: u32 = timeline.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum();
+        assert_eq!(samples, 7);
+        timeline.reset();
+        assert_eq!(timeline.bucket_nanos(), BUCKET_NANOS);
+    }
+
+    #[test]
+    fn test_gc_thread() {
+        let timeline = GcTimeline::new();
+        assert!(!timeline.is_gc_thread());
+        let gc = std::thread::Builder::new()
+            .name("GC Thread#0".into())
+            .spawn(|| GcTimeline::new().is_gc_thread())
+            .unwrap();
+        assert!(gc.join().unwrap());
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    ffi::c_void,
+    slice,
+};
+
+use crate::{
+    frame_type::FrameType,
+    jvmti_native::{jint, jmethodID, jvmtiAddrLocationMap},
+    vm::JVMPICallFrame,
+};
+
+/// keep the recent unloaded code, the older are only counted.
+const MAX_UNLOADED: usize = 1024;
+
+/// the compile_info records of the CompiledMethodLoad, see jvmticmlr.h.
+const JVMTI_CMLR_INLINE_INFO: u32 = 2;
+
+#[repr(C)]
+struct CompiledMethodLoadRecordHeader {
+    kind: u32,
+    majorinfoversion: jint,
+    minorinfoversion: jint,
+    next: *const CompiledMethodLoadRecordHeader,
+}
+
+#[repr(C)]
+struct PCStackInfo {
+    pc: *const c_void,
+    numstackframes: jint,
+    /// the innermost method first.
+    methods: *const jmethodID,
+    bcis: *const jint,
+}
+
+#[repr(C)]
+struct CompiledMethodLoadInlineRecord {
+    header: CompiledMethodLoadRecordHeader,
+    numpcs: jint,
+    pcinfo: *const PCStackInfo,
+}
+
+/// the inlined scopes at the pc, the pc is the end of the instruction like the return address.
+pub struct PcScope {
+    pub pc: usize,
+    /// (method, bci), the innermost first.
+    pub frames: Box<[(jmethodID, i32)]>,
+}
+
+/// the compiled java method in the code heap.
+pub struct JitCode {
+    pub method: jmethodID,
+    pub start: usize,
+    pub size: usize,
+    /// the compile level, 1-3 is C1, 4 is C2, 0 if unknown.
+    pub level: i32,
+    /// the nano time of the CompiledMethodLoad.
+    pub load_time: u64,
+    /// the nano time of the CompiledMethodUnload, or the code is overwritten by the new load.
+    pub unload_time: Option<u64>,
+    /// the inlined scopes sorted by the pc, from the inline records of the compile_info.
+    pub scopes: Box<[PcScope]>,
+    /// (start address, bci) sorted by the address, from the jvmtiAddrLocationMap.
+    pub locations: Box<[(usize, i32)]>,
+    /// the (caller, callee) of the inlined calls, sorted.
+    pub inlines: Box<[(jmethodID, jmethodID)]>,
+}
+
+impl JitCode {
+    pub fn new(method: jmethodID, start: usize, size: usize, level: i32, load_time: u64) -> Self {
+        Self {
+            method,
+            start,
+            size,
+            level,
+            load_time,
+            unload_time: None,
+            scopes: Box::new([]),
+            locations: Box::new([]),
+            inlines: Box::new([]),
+        }
+    }
+
+    /// the copy without the scopes and the locations.
+    pub fn brief(&self) -> Self {
+        Self {
+            unload_time: self.unload_time,
+            ..Self::new(self.method, self.start, self.size, self.level, self.load_time)
+        }
+    }
+
+    /// keep the location map, the jvmti frees it after the CompiledMethodLoad.
+    pub fn set_locations(&mut self, map: &[jvmtiAddrLocationMap]) {
+        let mut locations: Vec<(usize, i32)> = map
+            .iter()
+            .map(|loc| (loc.start_address as usize, loc.location as i32))
+            .collect();
+        locations.sort_by_key(|(start, _)| *start);
+        self.locations = locations.into_boxed_slice();
+    }
+
+    /// parse the inline records of the compile_info.
+    pub unsafe fn set_compile_info(&mut self, compile_info: *const c_void) {
+        let mut scopes = Vec::new();
+        let mut header = compile_info as *const CompiledMethodLoadRecordHeader;
+        while !header.is_null() {
+            if (*header).kind == JVMTI_CMLR_INLINE_INFO {
+                let record = &*(header as *const CompiledMethodLoadInlineRecord);
+                if !record.pcinfo.is_null() {
+                    for info in slice::from_raw_parts(record.pcinfo, record.numpcs.max(0) as _) {
+                        let n = info.numstackframes.max(0) as usize;
+                        if n == 0 || info.methods.is_null() || info.bcis.is_null() {
+                            continue;
+                        }
+                        let methods = slice::from_raw_parts(info.methods, n);
+                        let bcis = slice::from_raw_parts(info.bcis, n);
+                        scopes.push(PcScope {
+                            pc: info.pc as _,
+                            frames: methods.iter().copied().zip(bcis.iter().copied()).collect(),
+                        });
+                    }
+                }
+            }
+            header = (*header).next;
+        }
+        scopes.sort_by_key(|scope| scope.pc);
+        let mut inlines: Vec<(jmethodID, jmethodID)> = scopes
+            .iter()
+            .flat_map(|scope| scope.frames.windows(2).map(|w| (w[1].0, w[0].0)))
+            .collect();
+        inlines.sort();
+        inlines.dedup();
+        self.scopes = scopes.into_boxed_slice();
+        self.inlines = inlines.into_boxed_slice();
+    }
+
+    #[inline(always)]
+    pub fn inlines(&self, caller: jmethodID, callee: jmethodID) -> bool {
+        self.inlines.binary_search(&(caller, callee)).is_ok()
+    }
+
+    /// translate the pc into the java frames, the innermost first, return the frame number.
+    /// the inline records map the pc to the first scope ends after it, the location map
+    /// only knows the bci of the compiled method. called in the signal handler.
+    pub fn frames_at(&self, pc: usize, frames: &mut [JVMPICallFrame]) -> usize {
+        if frames.is_empty() {
+            return 0;
+        }
+        let idx = self.scopes.partition_point(|scope| scope.pc < pc);
+        if let Some(scope) = self.scopes.get(idx) {
+            let n = scope.frames.len().min(frames.len());
+            for (i, (method, bci)) in scope.frames[..n].iter().enumerate() {
+                let frame_type = if i + 1 == scope.frames.len() {
+                    FrameType::Compiled(self.level)
+                } else {
+                    FrameType::Inlined
+                };
+                frames[i] = JVMPICallFrame {
+                    bci: frame_type.encode(*bci),
+                    method_id: *method,
+                };
+            }
+            return n;
+        }
+        let idx = self.locations.partition_point(|(start, _)| *start <= pc);
+        let bci = if idx > 0 { self.locations[idx - 1].1 } else { 0 };
+        frames[0] = JVMPICallFrame {
+            bci: FrameType::Compiled(self.level).encode(bci),
+            method_id: self.method,
+        };
+        1
+    }
+
+    #[inline(always)]
+    pub fn end(&self) -> usize {
+        self.start + self.size
+    }
+
+    #[inline(always)]
+    pub fn contains(&self, pc: usize) -> bool {
+        self.start <= pc && pc < self.end()
+    }
+}
+
+/// Track the compiled methods by the CompiledMethodLoad and CompiledMethodUnload.
+/// The flushed nmethod may be reused by the new method before its unload is posted,
+/// so the overlapped live code is unloaded when the new code is loaded.
+pub struct JitCodeMap {
+    /// the live code by the start address.
+    live: BTreeMap<usize, JitCode>,
+    /// the start of the latest live code by the method.
+    methods: HashMap<jmethodID, usize>,
+    unloaded: VecDeque<JitCode>,
+    total_loads: u64,
+    total_unloads: u64,
+}
+
+impl JitCodeMap {
+    pub fn new() -> Self {
+        Self {
+            live: BTreeMap::new(),
+            methods: HashMap::new(),
+            unloaded: VecDeque::new(),
+            total_loads: 0,
+            total_unloads: 0,
+        }
+    }
+
+    /// the code replayed by the GenerateEvents is already live and kept, return false for it.
+    pub fn load(&mut self, code: JitCode) -> bool {
+        if self.live.get(&code.start).is_some_and(|live| live.method == code.method && live.size == code.size) {
+            return false;
+        }
+        let (start, end) = (code.start, code.end());
+        let overlapped: Vec<usize> = self
+            .live
+            .range(..end)
+            .rev()
+            .take_while(|(_, code)| code.end() > start)
+            .map(|(start, _)| *start)
+            .collect();
+        for addr in overlapped {
+            self.retire(addr, code.load_time);
+        }
+        self.total_loads += 1;
+        self.methods.insert(code.method, start);
+        self.live.insert(start, code);
+        true
+    }
+
+    /// the unload of the replaced code may be posted after the new code is loaded at
+    /// the same address, so the code is only unloaded if the method matches.
+    pub fn unload(&mut self, method: jmethodID, start: usize, time: u64) -> bool {
+        if self.live.get(&start).is_none_or(|code| code.method != method) {
+            return false;
+        }
+        self.retire(start, time)
+    }
+
+    fn retire(&mut self, start: usize, time: u64) -> bool {
+        let mut code = match self.live.remove(&start) {
+            Some(code) => code,
+            None => return false,
+        };
+        if self.methods.get(&code.method) == Some(&start) {
+            self.methods.remove(&code.method);
+        }
+        code.unload_time = Some(time);
+        self.total_unloads += 1;
+        if self.unloaded.len() >= MAX_UNLOADED {
+            self.unloaded.pop_front();
+        }
+        self.unloaded.push_back(code);
+        true
+    }
+
+    /// find the live code of the pc.
+    pub fn find(&self, pc: usize) -> Option<&JitCode> {
+        self.live
+            .range(..=pc)
+            .next_back()
+            .map(|(_, code)| code)
+            .filter(|code| code.contains(pc))
+    }
+
+    /// the latest live code of the method.
+    pub fn find_method(&self, method: jmethodID) -> Option<&JitCode> {
+        self.methods.get(&method).and_then(|start| self.live.get(start))
+    }
+
+    /// classify the caller frames by the live code of their methods, from the outermost.
+    /// the frame is inlined if the code of the enclosing compiled frame inlines it into its
+    /// caller, else compiled if its method has the code, else interpreted. The frame only knows
+    /// the method, so the activation running before the method compiled is taken as compiled.
+    /// called in the signal handler.
+    pub fn fill_frame_types(&self, frames: &mut [JVMPICallFrame]) {
+        let java = frames.iter().take_while(|f| f.is_java()).count();
+        let mut enclosing: Option<&JitCode> = None;
+        for i in (0..java).rev() {
+            let method = frames[i].method_id;
+            let inlined = i + 1 < java && enclosing.is_some_and(|code| code.inlines(frames[i + 1].method_id, method));
+            let frame_type = if inlined {
+                FrameType::Inlined
+            } else {
+                enclosing = self.find_method(method);
+                enclosing.map_or(FrameType::Interpreted, |code| FrameType::Compiled(code.level))
+            };
+            frames[i].bci = frame_type.encode(frames[i].bci);
+        }
+    }
+
+    #[inline(always)]
+    pub fn live(&self) -> impl Iterator<Item = &JitCode> {
+        self.live.values()
+    }
+
+    /// the recent unloaded code, the oldest first.
+    #[inline(always)]
+    pub fn unloaded(&self) -> impl Iterator<Item = &JitCode> {
+        self.unloaded.iter()
+    }
+
+    #[inline(always)]
+    pub fn total_loads(&self) -> u64 {
+        self.total_loads
+    }
+
+    #[inline(always)]
+    pub fn total_unloads(&self) -> u64 {
+        self.total_unloads
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vm::BCI_THREADID;
+
+    #[test]
+    fn test_load_unload() {
+        let mut map = JitCodeMap::new();
+        map.load(JitCode::new(0x1 as _, 0x1000, 0x100, 3, 10));
+        map.load(JitCode::new(0x2 as _, 0x1100, 0x80, 4, 20));
+        assert_eq!(map.find(0x10ff).map(|c| c.method), Some(0x1 as _));
+        assert_eq!(map.find(0x1100).map(|c| c.level), Some(4));
+        assert!(map.find(0x1180).is_none());
+        assert!(map.find(0xfff).is_none());
+
+        assert!(!map.unload(0x2 as _, 0x1000, 30));
+        assert!(map.unload(0x1 as _, 0x1000, 30));
+        assert!(!map.unload(0x1 as _, 0x1000, 31));
+        assert!(map.find(0x1000).is_none());
+        assert_eq!(map.unloaded().next().and_then(|c| c.unload_time), Some(30));
+
+        // the reused code heap replaces the overlapped code missed the unload.
+        map.load(JitCode::new(0x3 as _, 0x10c0, 0x100, 4, 40));
+        assert_eq!(map.find(0x1100).map(|c| c.method), Some(0x3 as _));
+        assert_eq!(map.live().count(), 1);
+        assert_eq!(map.total_loads(), 3);
+        assert_eq!(map.total_unloads(), 2);
+        // the late unload of the replaced method keeps the new code.
+        assert!(!map.unload(0x2 as _, 0x1100, 50));
+        assert_eq!(map.find(0x1100).map(|c| c.method), Some(0x3 as _));
+        // the replayed live code isn't counted as the reload.
+        map.load(JitCode::new(0x3 as _, 0x10c0, 0x100, 4, 60));
+        assert_eq!((map.total_loads(), map.total_unloads()), (3, 2));
+        assert_eq!(map.find(0x1100).map(|c| c.load_time), Some(40));
+    }
+
+    #[test]
+    fn test_fill_frame_types() {
+        let method = |id: usize| id as jmethodID;
+        let mut map = JitCodeMap::new();
+        let mut code = JitCode::new(method(4), 0x1000, 0x100, 4, 0);
+        code.inlines = Box::new([(method(3), method(2)), (method(4), method(3))]);
+        map.load(code);
+        map.load(JitCode::new(method(1), 0x2000, 0x100, 1, 0));
+        // 1 <- 2 <- 3 <- 4 <- 5, the innermost first.
+        let mut frames: Vec<JVMPICallFrame> = (1..=5)
+            .map(|id| JVMPICallFrame { bci: 7, method_id: method(id) })
+            .collect();
+        frames.push(JVMPICallFrame { bci: BCI_THREADID, method_id: method(42) });
+        map.fill_frame_types(&mut frames);
+        let types: Vec<FrameType> = frames[..5].iter().map(|f| FrameType::decode(f.bci).0).collect();
+        assert_eq!(
+            types,
+            [FrameType::Compiled(1), FrameType::Inlined, FrameType::Inlined, FrameType::Compiled(4), FrameType::Interpreted]
+        );
+        assert!(frames[..5].iter().all(|f| FrameType::decode(f.bci).1 == 7));
+        assert_eq!(frames[5].bci, BCI_THREADID);
+
+        // the unloaded method is interpreted again.
+        assert!(map.unload(method(4), 0x1000, 1));
+        assert!(map.find_method(method(4)).is_none());
+    }
+
+    #[test]
+    fn test_frames_at() {
+        let (outer, inner) = (0x1 as jmethodID, 0x2 as jmethodID);
+        let mut code = JitCode::new(outer, 0x1000, 0x100, 4, 0);
+        code.set_locations(&[
+            jvmtiAddrLocationMap { start_address: 0x1000 as _, location: 0 },
+            jvmtiAddrLocationMap { start_address: 0x1040 as _, location: 7 },
+        ]);
+        let mut frames = [JVMPICallFrame::default(); 4];
+        assert_eq!(code.frames_at(0x1050, &mut frames), 1);
+        assert_eq!(FrameType::decode(frames[0].bci), (FrameType::Compiled(4), 7));
+
+        let methods = [inner, outer];
+        let bcis = [3, 12];
+        let info = PCStackInfo {
+            pc: 0x1020 as _,
+            numstackframes: 2,
+            methods: methods.as_ptr(),
+            bcis: bcis.as_ptr(),
+        };
+        let record = CompiledMethodLoadInlineRecord {
+            header: CompiledMethodLoadRecordHeader {
+                kind: JVMTI_CMLR_INLINE_INFO,
+                majorinfoversion: 1,
+                minorinfoversion: 0,
+                next: std::ptr::null(),
+            },
+            numpcs: 1,
+            pcinfo: &info,
+        };
+        unsafe { code.set_compile_info(&record as *const _ as _) };
+        assert_eq!(code.frames_at(0x1010, &mut frames), 2);
+        assert_eq!(frames[0].method_id, inner);
+        assert_eq!(FrameType::decode(frames[0].bci), (FrameType::Inlined, 3));
+        assert_eq!(FrameType::decode(frames[1].bci), (FrameType::Compiled(4), 12));
+        // after the last scope, the location map is used.
+        assert_eq!(code.frames_at(0x1050, &mut frames), 1);
+        assert_eq!(frames[0].method_id, outer);
+    }
+}
@@ -143,6 +143,31 @@ impl JvmtiEnv {
         }
     }
 
+    pub fn get_source_file_name(
+        &self,
+        class: jclass,
+        source_name_ptr: *mut *mut i8,
+    ) -> Option<u32> {
+        unsafe {
+            (**self.0)
+                .GetSourceFileName
+                .map(|c| c(self.0, class, source_name_ptr))
+        }
+    }
+
+    pub fn get_line_number_table(
+        &self,
+        method_id: jmethodID,
+        count: *mut jint,
+        table: *mut *mut jvmtiLineNumberEntry,
+    ) -> Option<u32> {
+        unsafe {
+            (**self.0)
+                .GetLineNumberTable
+                .map(|c| c(self.0, method_id, count, table))
+        }
+    }
+
     pub fn deallocate(&self, p: *const i8) -> u32 {
         unsafe { (**self.0).Deallocate.map(|d| d(self.0, p as _)).unwrap() }
     }
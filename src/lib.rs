@@ -1,19 +1,40 @@
 mod vm;
+mod bci_report;
+mod call_trace_storage;
+mod call_tree;
+mod callgrind;
+mod chrome_trace;
 mod circle_queue;
+#[cfg(not(feature = "bench"))]
 mod code_cache;
+/// the code caches are only exported to the benches.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod code_cache;
 mod ctrl_svr;
 mod dwarf;
+mod gc_timeline;
+mod jit_code_map;
+mod json;
 mod jvmti;
 mod jvmti_native;
+mod method_dict;
+mod native_line;
 mod r#macro;
 mod os;
+mod pc_histogram;
+mod perf_map;
 mod profiler;
+pub mod profile_diff;
 mod signal_prof;
+mod speedscope;
 mod spinlock;
 mod stack_frame;
 mod stack_walker;
 mod symbol_parser;
+mod thread_timeline;
 mod frame_name;
+mod frame_type;
 mod vm_struct;
 mod walker_trace;
 
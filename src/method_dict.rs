@@ -0,0 +1,231 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::CStr;
+use std::ptr;
+
+use crate::{
+    cstr_2_str,
+    jvmti::JvmtiEnv,
+    jvmti_native::{jclass, jint, jmethodID, jvmtiLineNumberEntry},
+};
+
+/// the java method captured while the class is alive.
+pub struct MethodEntry {
+    /// the class name like java.lang.String
+    pub class: String,
+    pub name: String,
+    pub sig: String,
+    pub source_file: Option<String>,
+    /// (start bci, line number) sorted by the start bci.
+    pub line_table: Vec<(i64, i32)>,
+}
+
+impl MethodEntry {
+    /// find the line number of the bci from the line number table.
+    pub fn line_of(&self, bci: i32) -> Option<i32> {
+        let pos = self
+            .line_table
+            .partition_point(|(start, _)| *start <= bci as i64);
+        if pos == 0 {
+            return None;
+        }
+        Some(self.line_table[pos - 1].1)
+    }
+}
+
+/// Intern every jmethodID once into a stable method index.
+/// The methods are captured on the ClassPrepare, so the dump never calls jvmti
+/// for the jmethodID of the unloaded class. The jmethodID failed to resolve is
+/// remembered, so it's never passed to jvmti again.
+pub struct MethodDict {
+    ids: HashMap<jmethodID, u32>,
+    methods: Vec<MethodEntry>,
+    failed: HashSet<jmethodID>,
+}
+
+impl MethodDict {
+    pub fn new() -> Self {
+        Self {
+            ids: HashMap::new(),
+            methods: Vec::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn lookup(&self, method_id: jmethodID) -> Option<u32> {
+        self.ids.get(&method_id).copied()
+    }
+
+    #[inline(always)]
+    pub fn get(&self, idx: u32) -> Option<&MethodEntry> {
+        self.methods.get(idx as usize)
+    }
+
+    /// get the index of the method, resolve the method by jvmti if it's not interned.
+    pub fn intern(&mut self, jvmti: &JvmtiEnv, method_id: jmethodID) -> Option<u32> {
+        if let Some(idx) = self.lookup(method_id) {
+            return Some(idx);
+        }
+        if self.failed.contains(&method_id) {
+            return None;
+        }
+        match unsafe { Self::resolve(jvmti, method_id) } {
+            Some(entry) => Some(self.insert(method_id, entry)),
+            None => {
+                self.failed.insert(method_id);
+                None
+            }
+        }
+    }
+
+    /// intern all methods of the class, called by the ClassPrepare.
+    pub fn intern_class(&mut self, jvmti: &JvmtiEnv, methods: &[jmethodID]) {
+        for method_id in methods {
+            self.intern(jvmti, *method_id);
+        }
+    }
+
+    pub fn insert(&mut self, method_id: jmethodID, entry: MethodEntry) -> u32 {
+        self.failed.remove(&method_id);
+        let idx = self.methods.len() as u32;
+        self.methods.push(entry);
+        self.ids.insert(method_id, idx);
+        idx
+    }
+
+    unsafe fn resolve(jvmti: &JvmtiEnv, method_id: jmethodID) -> Option<MethodEntry> {
+        let mut method_name_ptr = ptr::null_mut();
+        let mut method_sig_ptr = ptr::null_mut();
+        let mut class_sig_ptr = ptr::null_mut();
+        let mut source_file_ptr = ptr::null_mut();
+        let mut class: jclass = ptr::null_mut();
+        let mut entry = None;
+        if 0 == jvmti.get_method_name(method_id, &mut method_name_ptr, &mut method_sig_ptr, ptr::null_mut())?
+            && 0 == jvmti.get_method_declaring_class(method_id, &mut class)?
+            && 0 == jvmti.get_class_signature(class, &mut class_sig_ptr, ptr::null_mut())?
+        {
+            let class_sig = cstr_2_str!(class_sig_ptr);
+            let mut class_name = Vec::new();
+            //trim the class Ljava/lang/String;
+            java_class_name(&class_sig.as_bytes()[1..class_sig.len() - 1], &mut class_name);
+            let source_file = match jvmti.get_source_file_name(class, &mut source_file_ptr) {
+                Some(0) => Some(cstr_2_str!(source_file_ptr).to_string()),
+                _ => None,
+            };
+            entry = Some(MethodEntry {
+                class: String::from_utf8_unchecked(class_name),
+                name: cstr_2_str!(method_name_ptr).to_string(),
+                sig: cstr_2_str!(method_sig_ptr).to_string(),
+                source_file,
+                line_table: Self::line_number_table(jvmti, method_id),
+            });
+        }
+        jvmti.deallocate(method_name_ptr as _);
+        jvmti.deallocate(method_sig_ptr as _);
+        jvmti.deallocate(class_sig_ptr as _);
+        jvmti.deallocate(source_file_ptr as _);
+        entry
+    }
+
+    /// the native and abstract method have no line number table.
+    unsafe fn line_number_table(jvmti: &JvmtiEnv, method_id: jmethodID) -> Vec<(i64, i32)> {
+        let mut count: jint = 0;
+        let mut table: *mut jvmtiLineNumberEntry = ptr::null_mut();
+        let mut line_table = Vec::new();
+        if let Some(0) = jvmti.get_line_number_table(method_id, &mut count, &mut table) {
+            for i in 0..count as usize {
+                let entry = &*table.add(i);
+                line_table.push((entry.start_location, entry.line_number));
+            }
+            line_table.sort_by_key(|(start, _)| *start);
+            jvmti.deallocate(table as _);
+        }
+        line_table
+    }
+}
+
+/// convert the class signature to the java name, like java/lang/String to java.lang.String
+/// and [I to int[].
+pub fn java_class_name(class: &[u8], name: &mut Vec<u8>) {
+    let start = name.len();
+    let mut array_dimension = 0;
+    while class[array_dimension] == b'[' {
+        array_dimension += 1;
+    }
+    if array_dimension == 0 {
+        name.extend_from_slice(class);
+    } else {
+        match class[array_dimension] {
+            b'B' => name.extend_from_slice(b"byte"),
+            b'C' => name.extend_from_slice(b"char"),
+            b'I' => name.extend_from_slice(b"int"),
+            b'J' => name.extend_from_slice(b"long"),
+            b'S' => name.extend_from_slice(b"short"),
+            b'Z' => name.extend_from_slice(b"boolean"),
+            b'F' => name.extend_from_slice(b"float"),
+            b'D' => name.extend_from_slice(b"double"),
+            _ => name.extend_from_slice(&class[array_dimension + 1..class.len() - 1]),
+        }
+    }
+    for _ in 0..array_dimension {
+        name.extend_from_slice(b"[]")
+    }
+    //replace the / to '.' like java/lang/String
+    for i in start..name.len() {
+        if name[i] == b'/' && !name.get(i + 1).is_some_and(|c| c.is_ascii_digit()) {
+            name[i] = b'.';
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_of() {
+        let method = MethodEntry {
+            class: "Foo".into(),
+            name: "bar".into(),
+            sig: "()V".into(),
+            source_file: Some("Foo.java".into()),
+            line_table: vec![(0, 10), (4, 11), (12, 15)],
+        };
+        assert_eq!(method.line_of(-1), None);
+        assert_eq!(method.line_of(0), Some(10));
+        assert_eq!(method.line_of(5), Some(11));
+        assert_eq!(method.line_of(100), Some(15));
+    }
+
+    #[test]
+    fn test_intern() {
+        let mut dict = MethodDict::new();
+        let entry = || MethodEntry {
+            class: "Foo".into(),
+            name: "bar".into(),
+            sig: "()V".into(),
+            source_file: None,
+            line_table: Vec::new(),
+        };
+        let id1 = 0x10 as jmethodID;
+        let id2 = 0x20 as jmethodID;
+        assert_eq!(dict.insert(id1, entry()), 0);
+        assert_eq!(dict.insert(id2, entry()), 1);
+        assert_eq!(dict.lookup(id1), Some(0));
+        assert_eq!(dict.lookup(0x30 as _), None);
+        assert_eq!(dict.get(1).map(|e| e.name.as_str()), Some("bar"));
+    }
+
+    #[test]
+    fn test_java_class_name() {
+        let mut name = Vec::new();
+        java_class_name(b"java/lang/String", &mut name);
+        assert_eq!(name, b"java.lang.String");
+        name.clear();
+        java_class_name(b"[[F", &mut name);
+        assert_eq!(name, b"float[][]");
+        name.clear();
+        java_class_name(b"[Ljava/lang/Object;", &mut name);
+        assert_eq!(name, b"java.lang.Object[]");
+    }
+}
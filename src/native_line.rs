@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use addr2line::Loader;
+
+use crate::code_cache::CodeCache;
+
+/// the source location of the native frame, one for each function of the inlined chain.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct NativeLine {
+    /// the mangled name of the function, the outermost may be missed in the debug info.
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+/// Resolve the native pc to file:line by the .debug_line and .debug_info of the library.
+/// The debug files are only loaded at dump time, the sampling only records the pc.
+pub struct NativeLines {
+    /// the loader of the library by the lib index, None if the debug file can't be loaded.
+    loaders: HashMap<u16, Option<Loader>>,
+}
+
+impl NativeLines {
+    pub fn new() -> Self {
+        Self {
+            loaders: HashMap::new(),
+        }
+    }
+
+    fn loader(&mut self, cc: &CodeCache) -> Option<&Loader> {
+        self.loaders
+            .entry(cc.lib_index())
+            .or_insert_with(|| Loader::new(cc.debug_file()?).ok())
+            .as_ref()
+    }
+
+    /// resolve the inlined chain of the pc into the lines, the outermost function first.
+    pub fn resolve(&mut self, cc: &CodeCache, pc: *const i8, lines: &mut Vec<NativeLine>) -> bool {
+        lines.clear();
+        if cc.debug_file().is_none() || cc.text_base().is_null() {
+            return false;
+        }
+        let probe = cc.link_address(pc);
+        let loader = match self.loader(cc) {
+            Some(loader) => loader,
+            None => return false,
+        };
+        let mut frames = match loader.find_frames(probe) {
+            Ok(frames) => frames,
+            Err(_) => return false,
+        };
+        // the frames are the innermost first.
+        while let Ok(Some(frame)) = frames.next() {
+            let function = frame
+                .function
+                .as_ref()
+                .and_then(|f| f.raw_name().ok())
+                .map(|name| name.into_owned());
+            let (file, line) = match frame.location {
+                Some(loc) => (loc.file.map(|f| f.to_string()), loc.line),
+                None => (None, None),
+            };
+            lines.push(NativeLine { function, file, line });
+        }
+        lines.reverse();
+        !lines.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::c_str;
+
+    #[inline(never)]
+    fn locate_me() -> usize {
+        locate_me as usize
+    }
+
+    #[test]
+    fn test_resolve() {
+        let pc = locate_me() as *const i8;
+        let mut info: libc::Dl_info = unsafe { std::mem::zeroed() };
+        assert_ne!(unsafe { libc::dladdr(pc as _, &mut info) }, 0);
+        let exe = std::env::current_exe().unwrap();
+        let mut cc = unsafe { CodeCache::new(c_str!("test"), 0) };
+        cc.set_text_base(info.dli_fbase as _);
+        cc.set_debug_file(exe.to_str().unwrap());
+
+        let mut resolver = NativeLines::new();
+        let mut lines = Vec::new();
+        assert!(resolver.resolve(&cc, pc, &mut lines));
+        let last = lines.last().unwrap();
+        assert!(last.file.as_deref().unwrap().ends_with("native_line.rs"));
+        assert!(last.function.as_deref().unwrap().contains("locate_me"));
+        assert!(last.line.is_some());
+    }
+}
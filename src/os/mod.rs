@@ -48,14 +48,37 @@ impl OS {
         OSImpl::thread_id()
     }
 
+    #[inline(always)]
+    pub fn nano_time() -> u64 {
+        OSImpl::nano_time()
+    }
+
     pub fn thread_state(tid: u32) -> ThreadState {
         unsafe { OSImpl::thread_state(tid) }
     }
+
+    /// the name of the current thread, return the name length without the NUL.
+    pub fn current_thread_name(buf: &mut [u8]) -> usize {
+        let ret = unsafe { libc::pthread_getname_np(libc::pthread_self(), buf.as_mut_ptr() as _, buf.len()) };
+        if ret != 0 {
+            return 0;
+        }
+        buf.iter().position(|b| *b == 0).unwrap_or(buf.len())
+    }
+
+    /// the pages of the range are mapped, the mincore fails with ENOMEM on the unmapped page.
+    pub fn is_mapped(addr: *const u8, len: usize) -> bool {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let start = addr as usize & !(page_size - 1);
+        let pages = (addr as usize + len.max(1) - start).div_ceil(page_size);
+        let mut vec = vec![0; pages];
+        unsafe { libc::mincore(start as _, pages * page_size, vec.as_mut_ptr() as _) == 0 }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::OSThreadList;
+    use super::{OSThreadList, OS};
 
     #[test]
     fn test_threads() {
@@ -76,4 +99,17 @@ mod test {
             count += 1;
         }
     }
+
+    #[test]
+    fn test_is_mapped() {
+        let code = [0u8; 64];
+        assert!(OS::is_mapped(code.as_ptr(), code.len()));
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let page = unsafe {
+            libc::mmap(std::ptr::null_mut(), page_size, libc::PROT_READ, libc::MAP_PRIVATE | libc::MAP_ANONYMOUS, -1, 0)
+        };
+        assert!(OS::is_mapped(page as _, page_size));
+        unsafe { libc::munmap(page, page_size) };
+        assert!(!OS::is_mapped(page as _, 16));
+    }
 }
@@ -21,7 +21,15 @@ impl OSImpl {
         }
     }
 
-    
+    /// monotonic nanoseconds, safe to call in the signal handler.
+    pub fn nano_time() -> u64 {
+        let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe {
+            libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+        }
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+
     unsafe fn native_send_thread_signal(tid: u32, signal: u32) -> bool {
         #[cfg(target_arch="aarch64")]
         {
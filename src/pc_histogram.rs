@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+const PC_TABLE_BITS: u32 = 16;
+const PC_TABLE_SIZE: usize = 1 << PC_TABLE_BITS;
+/// give up the sample if no free slot is found in the probes.
+const MAX_PROBES: usize = 64;
+
+/// Count the samples by the interrupted pc, grouped into the functions at dump time.
+/// The table is open addressed and only grows by CAS, so it's safe in the signal handler.
+pub struct PcHistogram {
+    enabled: AtomicBool,
+    pcs: Box<[AtomicUsize]>,
+    counts: Box<[AtomicU64]>,
+    dropped: AtomicU64,
+}
+
+impl PcHistogram {
+    pub fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            pcs: (0..PC_TABLE_SIZE).map(|_| AtomicUsize::new(0)).collect(),
+            counts: (0..PC_TABLE_SIZE).map(|_| AtomicU64::new(0)).collect(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// the table is cleared when enabled, the samples racing with the clear may be lost.
+    pub fn set_enabled(&self, enable: bool) {
+        if enable && !self.is_enabled() {
+            for (pc, count) in self.pcs.iter().zip(self.counts.iter()) {
+                pc.store(0, Ordering::Relaxed);
+                count.store(0, Ordering::Relaxed);
+            }
+            self.dropped.store(0, Ordering::Relaxed);
+        }
+        self.enabled.store(enable, Ordering::Release);
+    }
+
+    #[inline(always)]
+    fn slot(pc: usize) -> usize {
+        ((pc as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - PC_TABLE_BITS)) as usize
+    }
+
+    pub fn record(&self, pc: usize) {
+        if pc == 0 {
+            return;
+        }
+        let mut idx = Self::slot(pc);
+        for _ in 0..MAX_PROBES {
+            let slot = &self.pcs[idx];
+            let mut curr = slot.load(Ordering::Acquire);
+            if curr == 0 {
+                curr = match slot.compare_exchange(0, pc, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => pc,
+                    Err(curr) => curr,
+                };
+            }
+            if curr == pc {
+                self.counts[idx].fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            idx = (idx + 1) & (PC_TABLE_SIZE - 1);
+        }
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// forget the samples of the flushed code in the range, so the code loaded later at the
+    /// address isn't credited with them. the slots keep the pcs, the probe chains stay intact.
+    pub fn invalidate(&self, start: usize, end: usize) {
+        if !self.is_enabled() {
+            return;
+        }
+        for (pc, count) in self.pcs.iter().zip(self.counts.iter()) {
+            if (start..end).contains(&pc.load(Ordering::Acquire)) {
+                count.store(0, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// the (pc, samples) recorded.
+    pub fn entries(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.pcs
+            .iter()
+            .zip(self.counts.iter())
+            .map(|(pc, count)| (pc.load(Ordering::Acquire), count.load(Ordering::Relaxed)))
+            .filter(|(pc, count)| *pc != 0 && *count > 0)
+    }
+
+    #[inline(always)]
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let histogram = PcHistogram::new();
+        histogram.set_enabled(true);
+        for i in 0..100usize {
+            histogram.record(0x1000 + i % 10);
+        }
+        histogram.record(0);
+        let mut entries: Vec<_> = histogram.entries().collect();
+        entries.sort();
+        assert_eq!(entries.len(), 10);
+        assert!(entries.iter().all(|(_, count)| *count == 10));
+        assert_eq!(entries[0].0, 0x1000);
+
+        histogram.invalidate(0x1000, 0x1005);
+        assert_eq!(histogram.entries().count(), 5);
+        histogram.record(0x1000);
+        assert!(histogram.entries().any(|entry| entry == (0x1000, 1)));
+
+        histogram.set_enabled(false);
+        histogram.set_enabled(true);
+        assert_eq!(histogram.entries().count(), 0);
+        assert_eq!(histogram.dropped(), 0);
+    }
+}
@@ -0,0 +1,324 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    os::fd::AsRawFd,
+    ptr, slice,
+};
+
+use crate::{jvmti_native::jmethodID, log_warn, os::OS};
+
+const JITDUMP_MAGIC: u32 = 0x4A695444;
+const JITDUMP_VERSION: u32 = 1;
+const JITDUMP_HEADER_SIZE: u32 = 40;
+const JIT_CODE_LOAD: u32 = 0;
+const JIT_CODE_DEBUG_INFO: u32 = 2;
+const JIT_CODE_CLOSE: u32 = 3;
+/// the unloads are batched into a rewrite of the perf map at most every second.
+const MAP_REWRITE_NANOS: u64 = 1_000_000_000;
+
+#[cfg(target_arch = "x86_64")]
+const ELF_MACHINE: u32 = 62;
+#[cfg(target_arch = "aarch64")]
+const ELF_MACHINE: u32 = 183;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const ELF_MACHINE: u32 = 0;
+
+/// the source line of the pc in the jit code, written into the jitdump debug info.
+pub struct DebugLine<'a> {
+    pub addr: usize,
+    pub file: &'a str,
+    pub line: u32,
+}
+
+/// Write the jit code into the /tmp/perf-<pid>.map and /tmp/jit-<pid>.dump for the linux perf.
+/// The perf map is rewritten from the live code after the code is unloaded, the unloads are
+/// batched by the time and flushed by the profiler tick. The jitdump has no unload record,
+/// perf takes the latest load of the address by the timestamp.
+pub struct PerfMap {
+    /// the live code by the start address, (size, name, method), the method is null for the stubs.
+    code: BTreeMap<usize, (usize, String, jmethodID)>,
+    map_file: Option<BufWriter<File>>,
+    jitdump: Option<JitDump>,
+    /// the unloaded code is still in the perf map.
+    dirty: bool,
+    last_rewrite: u64,
+}
+
+impl PerfMap {
+    pub fn new() -> Self {
+        Self {
+            code: BTreeMap::new(),
+            map_file: None,
+            jitdump: None,
+            dirty: false,
+            last_rewrite: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.map_file.is_some() || self.jitdump.is_some()
+    }
+
+    #[inline(always)]
+    pub fn is_jitdump_enabled(&self) -> bool {
+        self.jitdump.is_some()
+    }
+
+    fn map_path() -> String {
+        format!("/tmp/perf-{}.map", std::process::id())
+    }
+
+    /// return true if the code generated before must be replayed, the code is known
+    /// and written by itself if the other output is already enabled.
+    pub fn enable_map(&mut self, enable: bool) -> io::Result<bool> {
+        let replay = enable && !self.is_enabled();
+        // the map left after disabled has no unloaded code.
+        if enable || self.dirty {
+            self.rewrite_map()?;
+        }
+        if !enable {
+            self.map_file = None;
+        }
+        self.retain_code();
+        Ok(replay)
+    }
+
+    /// the known code is written without the debug lines, they are only kept by the replay.
+    pub fn enable_jitdump(&mut self, enable: bool) -> io::Result<bool> {
+        let replay = enable && !self.is_enabled();
+        self.jitdump = None;
+        if enable {
+            let path = format!("/tmp/jit-{}.dump", std::process::id());
+            let mut jitdump = JitDump::create(&path)?;
+            for (start, (size, name, _)) in self.code.iter() {
+                jitdump.code_load(*start as _, *size, name, &[])?;
+            }
+            self.jitdump = Some(jitdump);
+        }
+        self.retain_code();
+        Ok(replay)
+    }
+
+    /// the live code is only kept while one of the outputs is enabled.
+    fn retain_code(&mut self) {
+        if !self.is_enabled() {
+            self.code.clear();
+        }
+    }
+
+    pub fn code_load(&mut self, addr: *const i8, size: usize, name: &str, method: jmethodID, lines: &[DebugLine]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let start = addr as usize;
+        if let Some(map_file) = self.map_file.as_mut() {
+            let _ = writeln!(map_file, "{start:x} {size:x} {name}").and_then(|_| map_file.flush());
+        }
+        if let Some(jitdump) = self.jitdump.as_mut() {
+            let _ = jitdump.code_load(addr, size, name, lines);
+        }
+        self.code.insert(start, (size, name.to_string(), method));
+    }
+
+    /// the code reloaded at the address by the other method is kept.
+    pub fn code_unload(&mut self, addr: *const i8, method: jmethodID) {
+        let start = addr as usize;
+        if self.code.get(&start).is_none_or(|(_, _, m)| *m != method) {
+            return;
+        }
+        self.code.remove(&start);
+        if self.map_file.is_some() {
+            self.dirty = true;
+            if OS::nano_time().saturating_sub(self.last_rewrite) >= MAP_REWRITE_NANOS {
+                self.flush();
+            }
+        }
+    }
+
+    /// rewrite the perf map if the code is unloaded since the last rewrite.
+    pub fn flush(&mut self) {
+        if !self.dirty || self.map_file.is_none() {
+            return;
+        }
+        if let Err(e) = self.rewrite_map() {
+            log_warn!("WARN: rewrite the perf map fail: {e}");
+        }
+    }
+
+    /// write the live code into the temp file then rename, perf never sees a partial map.
+    fn rewrite_map(&mut self) -> io::Result<()> {
+        let path = Self::map_path();
+        let tmp_path = format!("{path}.tmp");
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        for (start, (size, name, _)) in self.code.iter() {
+            writeln!(out, "{start:x} {size:x} {name}")?;
+        }
+        out.flush()?;
+        fs::rename(&tmp_path, &path)?;
+        let file = OpenOptions::new().append(true).open(&path)?;
+        self.map_file = Some(BufWriter::new(file));
+        self.dirty = false;
+        self.last_rewrite = OS::nano_time();
+        Ok(())
+    }
+}
+
+/// The jitdump file of perf, see tools/perf/Documentation/jitdump-specification.txt.
+/// The file is mapped executable, so perf record sees the mmap and finds it by perf inject.
+struct JitDump {
+    out: BufWriter<File>,
+    marker: *mut libc::c_void,
+    code_index: u64,
+}
+
+unsafe impl Send for JitDump {}
+
+impl JitDump {
+    fn create(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let marker = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                page_size,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if marker == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mut jitdump = Self {
+            out: BufWriter::new(file),
+            marker,
+            code_index: 0,
+        };
+        jitdump.write_header()?;
+        Ok(jitdump)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let out = &mut self.out;
+        out.write_all(&JITDUMP_MAGIC.to_ne_bytes())?;
+        out.write_all(&JITDUMP_VERSION.to_ne_bytes())?;
+        out.write_all(&JITDUMP_HEADER_SIZE.to_ne_bytes())?;
+        out.write_all(&ELF_MACHINE.to_ne_bytes())?;
+        out.write_all(&0u32.to_ne_bytes())?;
+        out.write_all(&std::process::id().to_ne_bytes())?;
+        out.write_all(&OS::nano_time().to_ne_bytes())?;
+        out.write_all(&0u64.to_ne_bytes())?;
+        out.flush()
+    }
+
+    fn write_record_header(&mut self, id: u32, size: usize, timestamp: u64) -> io::Result<()> {
+        self.out.write_all(&id.to_ne_bytes())?;
+        self.out.write_all(&(size as u32).to_ne_bytes())?;
+        self.out.write_all(&timestamp.to_ne_bytes())
+    }
+
+    /// the debug info must be written before the code load of the same code.
+    fn code_load(&mut self, addr: *const i8, size: usize, name: &str, lines: &[DebugLine]) -> io::Result<()> {
+        let timestamp = OS::nano_time();
+        if !lines.is_empty() {
+            let entries_size: usize = lines.iter().map(|l| 16 + l.file.len() + 1).sum();
+            self.write_record_header(JIT_CODE_DEBUG_INFO, 16 + 16 + entries_size, timestamp)?;
+            self.out.write_all(&(addr as u64).to_ne_bytes())?;
+            self.out.write_all(&(lines.len() as u64).to_ne_bytes())?;
+            for line in lines {
+                self.out.write_all(&(line.addr as u64).to_ne_bytes())?;
+                self.out.write_all(&line.line.to_ne_bytes())?;
+                self.out.write_all(&0u32.to_ne_bytes())?;
+                self.out.write_all(line.file.as_bytes())?;
+                self.out.write_all(&[0])?;
+            }
+        }
+        let code = unsafe { slice::from_raw_parts(addr as *const u8, size) };
+        self.write_record_header(JIT_CODE_LOAD, 16 + 40 + name.len() + 1 + size, timestamp)?;
+        self.out.write_all(&std::process::id().to_ne_bytes())?;
+        self.out.write_all(&OS::thread_id().to_ne_bytes())?;
+        self.out.write_all(&(addr as u64).to_ne_bytes())?;
+        self.out.write_all(&(addr as u64).to_ne_bytes())?;
+        self.out.write_all(&(size as u64).to_ne_bytes())?;
+        self.out.write_all(&self.code_index.to_ne_bytes())?;
+        self.out.write_all(name.as_bytes())?;
+        self.out.write_all(&[0])?;
+        self.out.write_all(code)?;
+        self.code_index += 1;
+        self.out.flush()
+    }
+}
+
+impl Drop for JitDump {
+    fn drop(&mut self) {
+        let _ = self.write_record_header(JIT_CODE_CLOSE, 16, OS::nano_time());
+        let _ = self.out.flush();
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        unsafe {
+            libc::munmap(self.marker, page_size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_perf_map() {
+        let code = [0x90u8; 64];
+        let addr = code.as_ptr() as *const i8;
+        let mut perf_map = PerfMap::new();
+        perf_map.code_load(addr, code.len(), "Foo.skipped()V", 0x1 as _, &[]);
+        assert!(perf_map.code.is_empty());
+
+        assert!(perf_map.enable_map(true).unwrap());
+        assert!(!perf_map.enable_jitdump(true).unwrap());
+        let lines = [DebugLine { addr: addr as usize, file: "Foo.java", line: 3 }];
+        perf_map.code_load(addr, 32, "Foo.bar()V", 0x2 as _, &lines);
+        perf_map.code_load(addr.wrapping_add(32), 32, "Foo.baz()V", 0x3 as _, &[]);
+        let map = fs::read_to_string(PerfMap::map_path()).unwrap();
+        assert!(map.contains(&format!("{:x} 20 Foo.bar()V\n", addr as usize)));
+
+        // the unload of the other method at the address is ignored.
+        perf_map.code_unload(addr, 0x3 as _);
+        assert!(perf_map.code.contains_key(&(addr as usize)));
+        perf_map.code_unload(addr, 0x2 as _);
+        // the enable just rewrote the map, the unload waits for the flush.
+        assert!(perf_map.dirty);
+        perf_map.flush();
+        assert!(!perf_map.dirty);
+        let map = fs::read_to_string(PerfMap::map_path()).unwrap();
+        assert_eq!(map, format!("{:x} 20 Foo.baz()V\n", addr as usize + 32));
+
+        let dump_path = format!("/tmp/jit-{}.dump", std::process::id());
+        perf_map.enable_jitdump(false).unwrap();
+        let dump = fs::read(&dump_path).unwrap();
+        assert_eq!(&dump[0..4], &JITDUMP_MAGIC.to_ne_bytes());
+        // the header, the debug info of 1 line, 2 code loads and the close.
+        let expected = 40 + (32 + 16 + 9) + 2 * (56 + 11 + 32) + 16;
+        assert_eq!(dump.len(), expected);
+
+        // the known code is written into the output enabled later, the replay isn't needed.
+        assert!(!perf_map.enable_jitdump(true).unwrap());
+        assert!(!perf_map.enable_map(true).unwrap());
+        let map = fs::read_to_string(PerfMap::map_path()).unwrap();
+        assert_eq!(map, format!("{:x} 20 Foo.baz()V\n", addr as usize + 32));
+        perf_map.enable_jitdump(false).unwrap();
+        let dump = fs::read(&dump_path).unwrap();
+        assert_eq!(dump.len(), 40 + (56 + 11 + 32) + 16);
+
+        perf_map.enable_map(false).unwrap();
+        assert!(perf_map.code.is_empty());
+        let _ = fs::remove_file(PerfMap::map_path());
+        let _ = fs::remove_file(dump_path);
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Write,
+};
+
+use crate::{frame_type::FrameType, json};
+
+const SVG_WIDTH: f64 = 1200f64;
+const SVG_PAD: f64 = 10f64;
+const FRAME_HEIGHT: f64 = 16f64;
+const FONT_SIZE: f64 = 12f64;
+/// the approximate width of a char of the font.
+const CHAR_WIDTH: f64 = 7f64;
+/// the frames narrower than it are not drawn.
+const MIN_FRAME_WIDTH: f64 = 0.1f64;
+
+/// the call traces saved by the agent, the frames are root first. the frame type suffixes
+/// are stripped, so the method compiled at another tier is still the same frame.
+pub struct Profile {
+    stacks: HashMap<String, u64>,
+    total: u64,
+}
+
+impl Profile {
+    /// parse the speedscope json or the collapsed format.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if text.trim_start().starts_with('{') {
+            Self::parse_speedscope(text)
+        } else {
+            Self::parse_collapsed(text)
+        }
+    }
+
+    fn add<'a, I: Iterator<Item = &'a str>>(&mut self, frames: I, samples: u64) {
+        let mut stack = String::new();
+        for (idx, frame) in frames.enumerate() {
+            if idx > 0 {
+                stack.push(';');
+            }
+            stack.push_str(FrameType::strip_suffix(frame));
+        }
+        *self.stacks.entry(stack).or_default() += samples;
+        self.total += samples;
+    }
+
+    /// parse the "frame;frame;frame samples" lines, the empty lines are skipped.
+    pub fn parse_collapsed(text: &str) -> Result<Self, String> {
+        let mut profile = Self { stacks: HashMap::new(), total: 0 };
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let (stack, samples) = line
+                .rsplit_once(' ')
+                .and_then(|(stack, samples)| Some((stack, samples.parse::<u64>().ok()?)))
+                .ok_or_else(|| format!("line {}: not the collapsed format", idx + 1))?;
+            profile.add(stack.split(';'), samples);
+        }
+        Ok(profile)
+    }
+
+    /// parse the speedscope json of the speedscope command, the thread profiles are merged.
+    /// the timed samples are counted once, the untimed samples are weighted by the counts.
+    pub fn parse_speedscope(text: &str) -> Result<Self, String> {
+        let json = json::parse(text)?;
+        let error = |what: &str| format!("not the speedscope format: {what}");
+        let frames: Vec<&str> = json
+            .get("shared")
+            .and_then(|shared| shared.get("frames"))
+            .and_then(|frames| frames.as_array())
+            .ok_or_else(|| error("no shared frames"))?
+            .iter()
+            .map(|frame| frame.get("name").and_then(|name| name.as_str()).ok_or_else(|| error("frame without name")))
+            .collect::<Result<_, _>>()?;
+        let profiles = json.get("profiles").and_then(|p| p.as_array()).ok_or_else(|| error("no profiles"))?;
+        let mut profile = Self { stacks: HashMap::new(), total: 0 };
+        for p in profiles {
+            let counted = p.get("unit").and_then(|unit| unit.as_str()) == Some("none");
+            let samples = p.get("samples").and_then(|s| s.as_array()).ok_or_else(|| error("no samples"))?;
+            let weights = p.get("weights").and_then(|w| w.as_array()).ok_or_else(|| error("no weights"))?;
+            for (stack, weight) in samples.iter().zip(weights) {
+                let stack = stack
+                    .as_array()
+                    .ok_or_else(|| error("bad sample"))?
+                    .iter()
+                    .map(|idx| idx.as_f64().and_then(|idx| frames.get(idx as usize).copied()))
+                    .collect::<Option<Vec<&str>>>()
+                    .ok_or_else(|| error("bad frame index"))?;
+                let samples = if counted { weight.as_f64().ok_or_else(|| error("bad weight"))? as u64 } else { 1 };
+                profile.add(stack.into_iter(), samples);
+            }
+        }
+        Ok(profile)
+    }
+
+    #[inline(always)]
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+/// the self and total share of the frame in both profiles, in percent.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FrameDelta {
+    pub name: String,
+    pub before_self: f64,
+    pub after_self: f64,
+    pub before_total: f64,
+    pub after_total: f64,
+}
+
+impl FrameDelta {
+    /// the change of the self share in the percentage points.
+    #[inline(always)]
+    pub fn delta(&self) -> f64 {
+        self.after_self - self.before_self
+    }
+}
+
+struct Node {
+    name: String,
+    before: f64,
+    after: u64,
+    children: BTreeMap<String, usize>,
+}
+
+/// Compare the profiles before and after the change. The before samples are scaled by the
+/// totals, so the profiles of the different lengths are compared by the share of the samples.
+pub struct ProfileDiff {
+    /// (the normalized before samples, the after samples) by the stack.
+    stacks: BTreeMap<String, (f64, u64)>,
+    before_total: u64,
+    after_total: u64,
+}
+
+impl ProfileDiff {
+    pub fn new(before: &Profile, after: &Profile) -> Self {
+        let scale = if before.total == 0 { 0f64 } else { after.total as f64 / before.total as f64 };
+        let mut stacks: BTreeMap<String, (f64, u64)> = BTreeMap::new();
+        for (stack, samples) in before.stacks.iter() {
+            stacks.entry(stack.clone()).or_default().0 = *samples as f64 * scale;
+        }
+        for (stack, samples) in after.stacks.iter() {
+            stacks.entry(stack.clone()).or_default().1 = *samples;
+        }
+        Self {
+            stacks,
+            before_total: before.total,
+            after_total: after.total,
+        }
+    }
+
+    /// the two columns collapsed format of the flamegraph.pl, "stack before after".
+    pub fn write_folded(&self, out: &mut String) {
+        for (stack, (before, after)) in self.stacks.iter() {
+            let _ = writeln!(out, "{stack} {} {after}", before.round() as u64);
+        }
+    }
+
+    /// the frames of both profiles, the recursive frame is counted once a stack.
+    pub fn frames(&self) -> Vec<FrameDelta> {
+        let percent = |n: f64| if self.after_total == 0 { 0f64 } else { n * 100f64 / self.after_total as f64 };
+        let mut frames: HashMap<&str, FrameDelta> = HashMap::new();
+        for (stack, (before, after)) in self.stacks.iter() {
+            let names: Vec<&str> = stack.split(';').collect();
+            let mut seen = HashSet::new();
+            for name in names.iter() {
+                if seen.insert(*name) {
+                    let frame = frames.entry(name).or_default();
+                    frame.before_total += percent(*before);
+                    frame.after_total += percent(*after as f64);
+                }
+            }
+            if let Some(leaf) = names.last() {
+                let frame = frames.entry(leaf).or_default();
+                frame.before_self += percent(*before);
+                frame.after_self += percent(*after as f64);
+            }
+        }
+        frames
+            .into_iter()
+            .map(|(name, frame)| FrameDelta { name: name.to_string(), ..frame })
+            .collect()
+    }
+
+    /// the biggest regressions and improvements of the self share, n of each.
+    pub fn report(&self, n: usize, out: &mut String) {
+        let mut frames = self.frames();
+        frames.sort_by(|a, b| b.delta().partial_cmp(&a.delta()).unwrap_or(Ordering::Equal).then(a.name.cmp(&b.name)));
+        let _ = writeln!(out, "samples: before {} after {}", self.before_total, self.after_total);
+        let header = |out: &mut String, title: &str| {
+            let _ = writeln!(out, "\n{title}:");
+            let _ = writeln!(
+                out, "{:>8} {:>8} {:>8} {:>8} {:>8}  name",
+                "delta", "self", "was", "total", "was"
+            );
+        };
+        let row = |out: &mut String, f: &FrameDelta| {
+            let _ = writeln!(
+                out, "{:>+7.2}% {:>7.2}% {:>7.2}% {:>7.2}% {:>7.2}%  {}",
+                f.delta(), f.after_self, f.before_self, f.after_total, f.before_total, f.name
+            );
+        };
+        header(out, "regressions");
+        for frame in frames.iter().take_while(|f| f.delta() > 0f64).take(n) {
+            row(out, frame);
+        }
+        header(out, "improvements");
+        for frame in frames.iter().rev().take_while(|f| f.delta() < 0f64).take(n) {
+            row(out, frame);
+        }
+    }
+
+    fn tree(&self) -> Vec<Node> {
+        let mut nodes = vec![Node {
+            name: "all".to_string(),
+            before: 0f64,
+            after: 0,
+            children: BTreeMap::new(),
+        }];
+        for (stack, (before, after)) in self.stacks.iter() {
+            let mut idx = 0;
+            nodes[0].before += before;
+            nodes[0].after += after;
+            for name in stack.split(';') {
+                idx = match nodes[idx].children.get(name) {
+                    Some(child) => *child,
+                    None => {
+                        nodes.push(Node {
+                            name: name.to_string(),
+                            before: 0f64,
+                            after: 0,
+                            children: BTreeMap::new(),
+                        });
+                        let child = nodes.len() - 1;
+                        nodes[idx].children.insert(name.to_string(), child);
+                        child
+                    }
+                };
+                nodes[idx].before += before;
+                nodes[idx].after += after;
+            }
+        }
+        nodes
+    }
+
+    /// the differential flame graph, the width is the after samples and the color is the change
+    /// of the samples, red for the grown and blue for the shrunk.
+    pub fn write_svg(&self, title: &str, out: &mut String) {
+        let nodes = self.tree();
+        // (node, depth, x) of the drawn frames.
+        let mut frames = Vec::new();
+        let mut stack = vec![(0usize, 0usize, 0u64)];
+        while let Some((idx, depth, x)) = stack.pop() {
+            frames.push((idx, depth, x));
+            let mut child_x = x;
+            for child in nodes[idx].children.values() {
+                stack.push((*child, depth + 1, child_x));
+                child_x += nodes[*child].after;
+            }
+        }
+        let total = nodes[0].after.max(1) as f64;
+        let scale = (SVG_WIDTH - 2f64 * SVG_PAD) / total;
+        frames.retain(|(idx, _, _)| nodes[*idx].after as f64 * scale >= MIN_FRAME_WIDTH);
+        let max_depth = frames.iter().map(|(_, depth, _)| *depth).max().unwrap_or(0);
+        let height = (max_depth + 1) as f64 * FRAME_HEIGHT + 3f64 * SVG_PAD + FONT_SIZE;
+        let max_delta = nodes
+            .iter()
+            .map(|n| (n.after as f64 - n.before).abs())
+            .fold(0f64, f64::max)
+            .max(1f64);
+
+        let _ = writeln!(
+            out,
+            r#"<?xml version="1.0" standalone="no"?>
+<svg version="1.1" width="{SVG_WIDTH}" height="{height}" xmlns="http://www.w3.org/2000/svg" font-family="Verdana" font-size="{FONT_SIZE}">
+<rect x="0" y="0" width="100%" height="100%" fill="white"/>"#
+        );
+        let _ = write!(
+            out,
+            r#"<text x="{}" y="{}" text-anchor="middle" font-size="{}">"#,
+            SVG_WIDTH / 2f64, SVG_PAD + FONT_SIZE, FONT_SIZE + 4f64
+        );
+        xml_escape(title, out);
+        out.push_str("</text>\n");
+        for (idx, depth, x) in frames {
+            let node = &nodes[idx];
+            let (x, y) = (SVG_PAD + x as f64 * scale, height - SVG_PAD - (depth + 1) as f64 * FRAME_HEIGHT);
+            let width = node.after as f64 * scale;
+            let delta = node.after as f64 - node.before;
+            let shade = (255f64 * (1f64 - delta.abs() / max_delta)).round() as u8;
+            let fill = if delta >= 0f64 {
+                format!("rgb(255,{shade},{shade})")
+            } else {
+                format!("rgb({shade},{shade},255)")
+            };
+            out.push_str("<g><title>");
+            xml_escape(&node.name, out);
+            let change = if node.before > 0f64 { delta * 100f64 / node.before } else { 100f64 };
+            let _ = write!(out, " (after {}, before {:.0}, {change:+.2}%)</title>", node.after, node.before);
+            let _ = write!(
+                out,
+                r#"<rect x="{x:.2}" y="{y:.2}" width="{width:.2}" height="{:.2}" fill="{fill}" rx="2" ry="2"/>"#,
+                FRAME_HEIGHT - 1f64
+            );
+            let chars = ((width - 6f64) / CHAR_WIDTH) as usize;
+            if chars >= 3 {
+                let _ = write!(out, r#"<text x="{:.2}" y="{:.2}">"#, x + 3f64, y + FRAME_HEIGHT - 4.5f64);
+                if node.name.chars().count() <= chars {
+                    xml_escape(&node.name, out);
+                } else {
+                    let name: String = node.name.chars().take(chars - 2).collect();
+                    xml_escape(&name, out);
+                    out.push_str("..");
+                }
+                out.push_str("</text>");
+            }
+            out.push_str("</g>\n");
+        }
+        out.push_str("</svg>\n");
+    }
+}
+
+fn xml_escape(s: &str, out: &mut String) {
+    for c in s.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let before = Profile::parse_collapsed("main;foo 50\nmain;bar 50\n").unwrap();
+        let after = Profile::parse_collapsed("main;foo 150\nmain;bar 40\nmain;Foo.<init> 10\n\n").unwrap();
+        assert_eq!(after.total(), 200);
+        assert!(Profile::parse_collapsed("main;foo\n").is_err());
+
+        let diff = ProfileDiff::new(&before, &after);
+        let mut folded = String::new();
+        diff.write_folded(&mut folded);
+        assert_eq!(folded, "main;Foo.<init> 0 10\nmain;bar 100 40\nmain;foo 100 150\n");
+
+        let mut frames = diff.frames();
+        frames.sort_by(|a, b| a.name.cmp(&b.name));
+        let foo = frames.iter().find(|f| f.name == "foo").unwrap();
+        assert_eq!((foo.before_self, foo.after_self), (50f64, 75f64));
+        let main = frames.iter().find(|f| f.name == "main").unwrap();
+        assert_eq!((main.before_total, main.after_total, main.delta()), (100f64, 100f64, 0f64));
+
+        let mut out = String::new();
+        diff.report(5, &mut out);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "samples: before 100 after 200");
+        assert!(lines[4].split_whitespace().eq(["+25.00%", "75.00%", "50.00%", "75.00%", "50.00%", "foo"]));
+        assert!(lines[5].ends_with("Foo.<init>"));
+        assert!(lines[9].split_whitespace().eq(["-30.00%", "20.00%", "50.00%", "20.00%", "50.00%", "bar"]));
+        assert_eq!(lines.len(), 10);
+
+        let mut svg = String::new();
+        diff.write_svg("diff", &mut svg);
+        assert!(svg.contains("<title>foo (after 150, before 100, +50.00%)</title>"));
+        assert!(svg.contains("Foo.&lt;init&gt;"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn test_parse() {
+        // the tier changed between the runs.
+        let before = Profile::parse("main_[0];Foo.bar()V_[j] 10\nmain_[0];Foo.bar()V_[1] 5\n").unwrap();
+        assert_eq!(before.stacks.get("main;Foo.bar()V"), Some(&15));
+
+        let mut speedscope = crate::speedscope::Speedscope::new();
+        let main = speedscope.frame("main_[0]");
+        let bar = speedscope.frame("Foo.bar()V_[2]");
+        let timed = speedscope.profile("worker", crate::speedscope::Unit::Nanoseconds, 0);
+        speedscope.add_sample(timed, &[main, bar], 10_000_000);
+        speedscope.add_sample(timed, &[main], 7_000_000);
+        let counted = speedscope.profile("other", crate::speedscope::Unit::None, 0);
+        speedscope.add_sample(counted, &[main, bar], 4);
+        let mut json = String::new();
+        speedscope.write("test", &mut json);
+        let after = Profile::parse(&json).unwrap();
+        assert_eq!(after.total(), 6);
+        assert_eq!(after.stacks.get("main;Foo.bar()V"), Some(&5));
+        assert_eq!(after.stacks.get("main"), Some(&1));
+        assert!(Profile::parse("{\"profiles\": []}").is_err());
+    }
+}
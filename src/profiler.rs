@@ -1,39 +1,71 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::CStr;
+use std::fmt::Write;
 use std::mem::MaybeUninit;
 use std::sync::atomic::Ordering;
 use std::sync::Mutex;
 use std::{mem, ptr};
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 
 use crate::cstr_2_str;
-use crate::frame_name::FrameName;
+use crate::call_trace_storage::{CallTraceStorage, TimeRange};
+use crate::callgrind::{CgFrame, Callgrind};
+use crate::call_tree::{CallTree, FlatProfile, ReportArgs};
+use crate::chrome_trace::ChromeTrace;
+use crate::frame_name::{Demangle, FrameDetail, FrameName};
+use crate::frame_type::FrameType;
+use crate::gc_timeline::GcTimeline;
+use crate::method_dict::{java_class_name, MethodDict};
+use crate::jit_code_map::{JitCode, JitCodeMap};
+use crate::pc_histogram::PcHistogram;
+use crate::bci_report::BciHistogram;
+use crate::perf_map::{DebugLine, PerfMap};
 use crate::jvmti::{JNIEnv, JvmtiEnv, JVMTI_THREAD_NORM_PRIORITY};
-use crate::jvmti_native::{jthread, jvmtiThreadInfo, jmethodID};
+use crate::jvmti_native::{jobject, jthread, jvmtiThreadInfo, jmethodID, jvmtiAddrLocationMap, JVMTI_EVENT_COMPILED_METHOD_LOAD};
 use crate::os::OS;
 use crate::signal_prof::{SigactionFn, SignalProf};
+use crate::speedscope::{Speedscope, Unit};
 use crate::spinlock::SpinLock;
 use crate::stack_frame::StackFrame;
 use crate::stack_walker::{StackContext, StackWalker};
 use crate::symbol_parser::SymbolParser;
+use crate::thread_timeline::ThreadTimeline;
 use crate::vm::{
-    JVMPICallFrame, JVMPICallTrace, MAX_FRAMES, MAX_NATIVE_FRAMES, RESERVED_FRAMES, BCI_THREADID, BCI_NATIVE_FRAME
+    JVMPICallFrame, JVMPICallTrace, MAX_FRAMES, MAX_NATIVE_FRAMES, RESERVED_FRAMES, BCI_THREADID, BCI_NATIVE_FRAME,
+    ASGCTFAIL_TICKS_GCACTIVE, BCI_GC, BCI_ERROR, BCI_CODE_BLOB, BCI_NATIVE_PC, ASGCTFAIL_TYPES, ASGCTFAIL_NAMES,
 };
 use crate::vm_struct::VMThread;
 use crate::walker_trace::WalkerTrace;
 use crate::{
     c_str,
-    code_cache::{CodeBlob, CodeCache},
+    code_cache::{CodeBlob, CodeCache, CodeCacheArray, CodeLookup},
 };
-use crate::{circle_queue::CircleQueue, get_vm_mut, log_info, VM};
+use crate::{circle_queue::CircleQueue, get_vm, get_vm_mut, log_info, VM};
 
 const DEFAULT_MIN_SIGNAL: u32 = 100_000_000;
 const DEFAULT_MAX_SIGNAL: u32 = 500_000_000;
 
 pub const MAX_CODE_CACHE_ARRAY: u32 = 2048;
 
+/// parse the libraries loaded after start and flush the perf map every 100 ticks, about 1 second.
+const SYMBOLS_REFRESH_TICKS: u32 = 100;
+
 const CONCURRENCY_LEVEL: usize = 16;
 
+/// the hottest offsets dumped for each function.
+const HOT_PCS_TOP: usize = 20;
+/// the code bytes dumped at the hot offset.
+const HOT_PC_BYTES: usize = 16;
+
+/// the samples of the function in the hot pcs dump.
+struct HotFunction {
+    start: usize,
+    end: usize,
+    samples: u64,
+    /// (pc, samples, the code bytes at the pc)
+    pcs: Vec<(usize, u64, Vec<u8>)>,
+}
+
 pub struct ThreadInfo {
     pub jthread_id: u64,
     pub name: String,
@@ -44,15 +76,29 @@ pub struct Profiler {
     running: AtomicBool,
     queue: CircleQueue,
     calltrace_buffer: Vec<Vec<JVMPICallFrame>>,
-    code_caches: Vec<CodeCache>,
+    code_caches: CodeCacheArray,
     walker_trace: WalkerTrace,
     locks: Vec<SpinLock>,
     stub_lock: SpinLock,
     runtime_stub: CodeCache,
+    jit_lock: SpinLock,
+    jit_code: JitCodeMap,
     call_stub_begin: *const i8,
     call_stub_end: *const i8,
     max_frames: usize,
     jthreads: Mutex<HashMap<u64, ThreadInfo>>,
+    gc_timeline: GcTimeline,
+    thread_timeline: ThreadTimeline,
+    total_samples: AtomicU64,
+    asgct_failures: [AtomicU64; ASGCTFAIL_TYPES],
+    method_dict: Mutex<MethodDict>,
+    frame_detail: FrameDetail,
+    demangle: Demangle,
+    /// index the kernel functions of the kallsyms when the profiler starts.
+    kernel_symbols: bool,
+    storage: Mutex<CallTraceStorage>,
+    perf_map: Mutex<PerfMap>,
+    pc_histogram: PcHistogram,
 }
 
 impl Profiler {
@@ -70,7 +116,7 @@ impl Profiler {
         let locks = (0..CONCURRENCY_LEVEL).map(
             |_| SpinLock::new()
         ).collect();
-        let runtime_stub = CodeCache::new(c_str!("[stubs]"), -1 as _);
+        let runtime_stub = unsafe { CodeCache::new(c_str!("[stubs]"), -1 as _) };
         Self {
             locks,
             queue,
@@ -79,24 +125,37 @@ impl Profiler {
             walker_trace,
             max_frames,
             runtime_stub,
+            jit_lock: SpinLock::new(),
+            jit_code: JitCodeMap::new(),
             call_stub_begin: ptr::null(),
             call_stub_end: ptr::null(),
             calltrace_buffer,
-            code_caches: Vec::new(),
+            code_caches: CodeCacheArray::new(MAX_CODE_CACHE_ARRAY as _),
             stub_lock: SpinLock::new(),
             jthreads: Mutex::new(HashMap::new()),
+            gc_timeline: GcTimeline::new(),
+            thread_timeline: ThreadTimeline::new(),
+            total_samples: AtomicU64::new(0),
+            asgct_failures: Default::default(),
+            method_dict: Mutex::new(MethodDict::new()),
+            frame_detail: FrameDetail::Method,
+            demangle: Demangle::default(),
+            kernel_symbols: false,
+            storage: Mutex::new(CallTraceStorage::new()),
+            perf_map: Mutex::new(PerfMap::new()),
+            pc_histogram: PcHistogram::new(),
         }
     }
 
     #[inline(always)]
-    pub fn update_symbols(&mut self, parse_kernel: bool) {
-        SymbolParser::instance().parse_libraries(&mut self.code_caches, parse_kernel);
+    pub fn update_symbols(&self, parse_kernel: bool) {
+        SymbolParser::instance().parse_libraries(&self.code_caches, parse_kernel);
     }
 
+    /// the published code cache is never moved or freed while the profiler lives.
     pub fn find_lib_by_address(&self, addr: *const i8) -> Option<&'static CodeCache> {
         self.code_caches
-            .iter()
-            .find(|code_cache| code_cache.contains(addr))
+            .find(addr)
             .map(|c| unsafe { mem::transmute(c) })
     }
 
@@ -104,7 +163,16 @@ impl Profiler {
         if self.running.load(Ordering::Acquire) {
             return;
         }
-        self.update_symbols(false);
+        self.update_symbols(self.kernel_symbols);
+        self.gc_timeline.reset();
+        self.thread_timeline.reset();
+        if let Ok(mut storage) = self.storage.lock() {
+            storage.clear();
+        }
+        self.total_samples.store(0, Ordering::Relaxed);
+        self.asgct_failures
+            .iter()
+            .for_each(|c| c.store(0, Ordering::Relaxed));
         let jthr = VM::new_java_thread(jni, c_str!("Agent Profiler Thread")).unwrap();
         let jvmti = get_vm_mut().jvmti();
         jvmti.run_agent_thread(
@@ -116,6 +184,13 @@ impl Profiler {
         self.running.store(true, Ordering::Release);
     }
 
+    /// intern the methods of the prepared class, so they can be named after the class unloaded.
+    pub fn intern_methods(&self, jvmti: &JvmtiEnv, methods: &[jmethodID]) {
+        if let Ok(mut dict) = self.method_dict.lock() {
+            dict.intern_class(jvmti, methods);
+        }
+    }
+
     pub fn stop(&mut self) {
         log_info!("INFO: profiler stop.");
         self.walker_trace.stop();
@@ -124,7 +199,10 @@ impl Profiler {
 
     pub unsafe fn add_runtime_stub(&mut self, name: *const i8, address: *const i8, len: u32) {
         self.stub_lock.lock().map(|_| {
+            // the stubs are mostly generated in the address order, the sort is cheap.
             self.runtime_stub.add(address, len as _, name, true);
+            self.runtime_stub.sort();
+            self.code_caches.set_stub_range(self.runtime_stub.min_address, self.runtime_stub.max_address);
         });
         let name_str = cstr_2_str!(name);
         if name_str == "call_stub" {
@@ -132,49 +210,705 @@ impl Profiler {
             self.call_stub_end = address.add(len as _);
         }
         get_vm_mut().update_heap_bounds(address, address.add(len as _));
+        if let Ok(mut perf_map) = self.perf_map.lock() {
+            perf_map.code_load(address, len as _, name_str, ptr::null_mut(), &[]);
+        }
     }
 
-    pub unsafe fn add_java_method(&mut self, address: *const i8, len: u32) {
+    pub unsafe fn add_java_method(
+        &mut self,
+        method: jmethodID,
+        address: *const i8,
+        len: u32,
+        map: &[jvmtiAddrLocationMap],
+        compile_info: *const libc::c_void,
+    ) {
         get_vm_mut().update_heap_bounds(address, address.add(len as _));
+        let level = get_vm()
+            .code_heap()
+            .find_nmethod(address)
+            .filter(|nmethod| nmethod.is_nmethod())
+            .map(|nmethod| nmethod.level())
+            .unwrap_or(0);
+        let mut code = JitCode::new(method, address as _, len as _, level, OS::nano_time());
+        code.set_locations(map);
+        code.set_compile_info(compile_info);
+        // the samples in the range belong to the flushed code whose unload is missed.
+        if self.jit_lock.lock().is_some_and(|_guard| self.jit_code.load(code)) {
+            self.pc_histogram.invalidate(address as _, address.add(len as _) as _);
+        }
+        self.write_perf_map_method(method, address, len as _, map);
+    }
+
+    pub fn remove_java_method(&mut self, method: jmethodID, address: *const i8) {
+        let end = self.jit_lock.lock().and_then(|_guard| {
+            let end = self.jit_code.find(address as _).filter(|code| code.method == method)?.end();
+            self.jit_code.unload(method, address as _, OS::nano_time()).then_some(end)
+        });
+        if let Some(end) = end {
+            self.pc_histogram.invalidate(address as _, end);
+        }
+        if let Ok(mut perf_map) = self.perf_map.lock() {
+            perf_map.code_unload(address, method);
+        }
+    }
+
+    /// the java frames of the pc in the compiled method with the inlined callees, innermost first.
+    /// return 0 if the pc isn't compiled code or the jit code is being updated.
+    pub fn find_jit_frames(&self, pc: *const i8, frames: &mut [JVMPICallFrame]) -> usize {
+        let _guard = match self.jit_lock.try_lock_with_guard() {
+            Some(guard) => guard,
+            None => return 0,
+        };
+        self.jit_code
+            .find(pc as _)
+            .map_or(0, |code| code.frames_at(pc as _, frames))
+    }
+
+    #[inline(always)]
+    pub fn enable_pc_histogram(&self, enable: bool) {
+        self.pc_histogram.set_enabled(enable);
+    }
+
+    /// dump the hottest offsets of the functions whose name contains the pattern,
+    /// the native function by the code blob and the java method by the jit code.
+    pub fn dump_hot_pcs(&self, pattern: &str, out: &mut String) {
+        // the same name may be compiled at the different addresses, or the overloads in the libraries.
+        let mut functions: HashMap<(String, usize), HotFunction> = HashMap::new();
+        let mut name = Vec::new();
+        for (pc, count) in self.pc_histogram.entries() {
+            name.clear();
+            let (start, end, bytes) = if let Some((code, bytes)) = self.copy_jit_code(pc) {
+                if let Some(m) = self.method_dict.lock().ok().and_then(|mut dict| {
+                    let idx = dict.intern(get_vm().jvmti(), code.method)?;
+                    dict.get(idx).map(|m| format!("{}.{}{}", m.class, m.name, m.sig))
+                }) {
+                    name.extend_from_slice(m.as_bytes());
+                }
+                (code.start, code.end(), bytes)
+            } else if let Some(blob) = self.find_native_method(pc as _) {
+                self.demangle.decode(blob.name_str().as_bytes(), &mut name);
+                let end = blob.start() as usize + blob.size();
+                let len = HOT_PC_BYTES.min(end.saturating_sub(pc));
+                // the library may be unloaded after the sample.
+                let bytes = if OS::is_mapped(pc as _, len) {
+                    unsafe { std::slice::from_raw_parts(pc as *const u8, len) }.to_vec()
+                } else {
+                    Vec::new()
+                };
+                (blob.start() as usize, end, bytes)
+            } else {
+                continue;
+            };
+            let name = String::from_utf8_lossy(&name);
+            if !name.contains(pattern) {
+                continue;
+            }
+            let function = functions.entry((name.into_owned(), start)).or_insert(HotFunction {
+                start,
+                end,
+                samples: 0,
+                pcs: Vec::new(),
+            });
+            function.samples += count;
+            function.pcs.push((pc, count, bytes));
+        }
+        if functions.is_empty() {
+            let _ = writeln!(out, "no samples of {pattern}, dropped {}", self.pc_histogram.dropped());
+            return;
+        }
+        let mut functions: Vec<_> = functions.into_iter().collect();
+        functions.sort_by_key(|((_, start), f)| (std::cmp::Reverse(f.samples), *start));
+        for ((name, _), mut f) in functions {
+            let _ = writeln!(out, "{name} [{:#x}, {:#x}) samples: {}", f.start, f.end, f.samples);
+            f.pcs.sort_by_key(|(_, count, _)| std::cmp::Reverse(*count));
+            for (pc, count, bytes) in f.pcs.into_iter().take(HOT_PCS_TOP) {
+                let percent = count as f64 * 100f64 / f.samples as f64;
+                let _ = write!(out, "  +{:<#8x} {count:>8} {percent:>6.2}% ", pc - f.start);
+                for b in bytes {
+                    let _ = write!(out, " {b:02x}");
+                }
+                out.push('\n');
+            }
+        }
+    }
+
+    /// the brief of the live compiled method of the pc and the code bytes at the pc, the bytes
+    /// are copied under the lock, the code heap may be reused once the method is unloaded.
+    fn copy_jit_code(&self, pc: usize) -> Option<(JitCode, Vec<u8>)> {
+        let _guard = self.jit_lock.lock()?;
+        let code = self.jit_code.find(pc)?;
+        let len = HOT_PC_BYTES.min(code.end().saturating_sub(pc));
+        let bytes = unsafe { std::slice::from_raw_parts(pc as *const u8, len) }.to_vec();
+        Some((code.brief(), bytes))
+    }
+
+    /// list the live compiled methods and the recent unloaded.
+    pub fn dump_jit(&self, out: &mut String) {
+        let now = OS::nano_time();
+        // copy the code out, the compiler threads spin on the lock.
+        let (live, unloaded, loads, unloads) = match self.jit_lock.lock() {
+            Some(_guard) => (
+                self.jit_code.live().map(JitCode::brief).collect::<Vec<_>>(),
+                self.jit_code.unloaded().map(JitCode::brief).collect::<Vec<_>>(),
+                self.jit_code.total_loads(),
+                self.jit_code.total_unloads(),
+            ),
+            None => return,
+        };
+        let mut dict = match self.method_dict.lock() {
+            Ok(dict) => dict,
+            Err(_) => return,
+        };
+        let jvmti = get_vm().jvmti();
+        let mut method_name = |method: jmethodID| {
+            dict.intern(jvmti, method)
+                .and_then(|idx| dict.get(idx))
+                .map(|m| format!("{}.{}{}", m.class, m.name, m.sig))
+                .unwrap_or_else(|| "[unknown]".to_string())
+        };
+        let live_bytes: usize = live.iter().map(|code| code.size).sum();
+        let _ = writeln!(out, "live: {} methods, {live_bytes} bytes (loaded {loads}, unloaded {unloads})", live.len());
+        let _ = writeln!(out, "{:<18} {:>8} {:>5} {:>10} name", "address", "size", "level", "age(ms)");
+        for code in live {
+            let age = now.saturating_sub(code.load_time) / 1_000_000;
+            let _ = writeln!(
+                out, "{:<#18x} {:>8} {:>5} {age:>10} {}",
+                code.start, code.size, code.level, method_name(code.method)
+            );
+        }
+        let _ = writeln!(out, "recently unloaded: {}", unloaded.len());
+        let _ = writeln!(out, "{:<18} {:>8} {:>5} {:>10} name", "address", "size", "level", "life(ms)");
+        for code in unloaded {
+            let life = code.unload_time.unwrap_or(now).saturating_sub(code.load_time) / 1_000_000;
+            let _ = writeln!(
+                out, "{:<#18x} {:>8} {:>5} {life:>10} {}",
+                code.start, code.size, code.level, method_name(code.method)
+            );
+        }
+    }
+
+    /// the jitdump gets the source lines of the code from the location map.
+    fn write_perf_map_method(&self, method: jmethodID, address: *const i8, len: usize, map: &[jvmtiAddrLocationMap]) {
+        let mut perf_map = match self.perf_map.lock() {
+            Ok(perf_map) if perf_map.is_enabled() => perf_map,
+            _ => return,
+        };
+        let mut dict = match self.method_dict.lock() {
+            Ok(dict) => dict,
+            Err(_) => return,
+        };
+        let entry = match dict.intern(get_vm().jvmti(), method).and_then(|idx| dict.get(idx)) {
+            Some(entry) => entry,
+            None => return,
+        };
+        let name = format!("{}.{}{}", entry.class, entry.name, entry.sig);
+        let mut lines = Vec::new();
+        if perf_map.is_jitdump_enabled() {
+            let file = entry.source_file.as_deref().unwrap_or("Unknown");
+            for loc in map {
+                if let Some(line) = entry.line_of(loc.location as _) {
+                    lines.push(DebugLine { addr: loc.start_address as _, file, line: line as _ });
+                }
+            }
+        }
+        perf_map.code_load(address, len, &name, method, &lines);
+    }
+
+    pub fn enable_perf_map(&self, enable: bool) -> std::io::Result<()> {
+        let replay = self.perf_map.lock().unwrap().enable_map(enable)?;
+        if replay {
+            self.replay_jit_code();
+        }
+        Ok(())
+    }
+
+    pub fn enable_jitdump(&self, enable: bool) -> std::io::Result<()> {
+        let replay = self.perf_map.lock().unwrap().enable_jitdump(enable)?;
+        if replay {
+            self.replay_jit_code();
+        }
+        Ok(())
+    }
+
+    /// write the code generated before the first output is enabled, the compiled methods
+    /// are replayed by the jvmti into the CompiledMethodLoad.
+    fn replay_jit_code(&self) {
+        if let (Some(_guard), Ok(mut perf_map)) = (self.stub_lock.lock(), self.perf_map.lock()) {
+            for blob in self.runtime_stub.code_blobs() {
+                perf_map.code_load(blob.start(), blob.size(), blob.name_str(), ptr::null_mut(), &[]);
+            }
+        }
+        get_vm().jvmti().generate_events(JVMTI_EVENT_COMPILED_METHOD_LOAD);
     }
 
     #[inline(always)]
     pub fn find_native_method(&self, pc: *const i8) -> Option<&CodeBlob> {
-        self.find_library_by_address(pc)
-            .and_then(|cc| cc.binary_search(pc))
+        match self.code_caches.lookup(pc)? {
+            CodeLookup::Library(cc) => cc.binary_search(pc),
+            CodeLookup::RuntimeStubs => self.find_runtime_stub(pc),
+        }
+    }
+
+    /// the stubs reported by the DynamicCodeGenerated, skip if the stubs are being updated.
+    pub fn find_runtime_stub(&self, pc: *const i8) -> Option<&CodeBlob> {
+        let _guard = self.stub_lock.try_lock_with_guard()?;
+        if !self.runtime_stub.contains(pc) {
+            return None;
+        }
+        self.runtime_stub.binary_search(pc)
+    }
+
+    /// the frame of the stub, adapter or interpreter in the code heap, the nmethod is java frame.
+    unsafe fn find_code_blob_frame(&self, pc: *const i8) -> Option<JVMPICallFrame> {
+        if let Some(blob) = self.find_runtime_stub(pc) {
+            return Some(JVMPICallFrame {
+                bci: BCI_CODE_BLOB,
+                method_id: blob.c_name() as _,
+            });
+        }
+        let nmethod = get_vm().code_heap().find_nmethod(pc)?;
+        let name = nmethod.name();
+        if nmethod.is_nmethod() || name.is_null() {
+            return None;
+        }
+        Some(JVMPICallFrame {
+            bci: BCI_CODE_BLOB,
+            method_id: name as _,
+        })
     }
 
     #[inline(always)]
     pub fn find_library_by_address(&self, pc: *const i8) -> Option<&CodeCache> {
-        self.code_caches.iter().find(|cc| cc.contains(pc))
+        self.code_caches.find(pc)
+    }
+
+    #[inline(always)]
+    pub fn set_frame_detail(&mut self, detail: FrameDetail) {
+        self.frame_detail = detail;
+    }
+
+    pub fn set_demangle(&mut self, demangle: Demangle) {
+        self.demangle = demangle;
+    }
+
+    /// the kernel symbols are parsed at the next start.
+    pub fn set_kernel_symbols(&mut self, on: bool) {
+        self.kernel_symbols = on;
+    }
+
+    #[inline(always)]
+    pub fn gc_timeline(&self) -> &GcTimeline {
+        &self.gc_timeline
+    }
+
+    #[inline(always)]
+    pub fn thread_timeline(&self) -> &ThreadTimeline {
+        &self.thread_timeline
+    }
+
+    /// record the lock wait start with the class of the monitor, called by the waiting thread.
+    pub fn monitor_contended_enter(&self, jvmti: &JvmtiEnv, jni: &JNIEnv, object: jobject) {
+        let time = OS::nano_time();
+        let mut class_name = Vec::new();
+        if let Some(class) = jni.get_class_object(object) {
+            let mut sig_ptr = ptr::null_mut();
+            if let Some(0) = jvmti.get_class_signature(class, &mut sig_ptr, ptr::null_mut()) {
+                let sig = cstr_2_str!(sig_ptr).as_bytes();
+                // Ljava/lang/Object; or the array like [I.
+                match sig.first() {
+                    Some(b'[') => java_class_name(sig, &mut class_name),
+                    Some(_) if sig.len() > 2 => java_class_name(&sig[1..sig.len() - 1], &mut class_name),
+                    _ => {}
+                }
+                jvmti.deallocate(sig_ptr as _);
+            }
+        }
+        let class = String::from_utf8(class_name).unwrap_or_default();
+        self.thread_timeline.lock_enter(OS::thread_id(), time, class);
     }
 
     #[inline(always)]
     pub fn push_trace(&mut self, trace: &JVMPICallTrace) {
-        self.queue.push(trace);
+        self.queue.push(trace, OS::nano_time(), OS::thread_id());
     }
 
     pub(crate) fn run(&mut self) {
         log_info!("INFO: profiler start.");
-        self.walker_trace.run();
+        let queue = &mut self.queue;
+        let storage = &self.storage;
+        let method_dict = &self.method_dict;
+        let code_caches = &self.code_caches;
+        let perf_map = &self.perf_map;
+        let kernel_symbols = self.kernel_symbols;
+        let jvmti = get_vm_mut().jvmti();
+        let mut ticks = 0u32;
+        // drain the traces pushed by the signal handler every tick,
+        // intern the methods while the classes are still alive.
+        self.walker_trace.run(|| {
+            ticks += 1;
+            if ticks >= SYMBOLS_REFRESH_TICKS {
+                ticks = 0;
+                // the parsed libraries are skipped, only the dlopen'ed ones are added.
+                SymbolParser::instance().parse_libraries(code_caches, kernel_symbols);
+                if let Ok(mut perf_map) = perf_map.lock() {
+                    perf_map.flush();
+                }
+            }
+            if let (Ok(mut storage), Ok(mut dict)) = (storage.lock(), method_dict.lock()) {
+                while queue.pop(|trace, time, tid| {
+                    storage.add(trace, time, tid);
+                    for i in 0..trace.num_frames.max(0) as usize {
+                        let frame = unsafe { &*trace.frames.add(i) };
+                        if frame.is_java() {
+                            dict.intern(jvmti, frame.method_id);
+                        }
+                    }
+                }) {}
+            }
+        });
+    }
+
+    /// dump the call traces in collapsed format, the frames are root first and splited by ';'.
+    /// only the samples in the range are dumped.
+    pub fn dump_collapsed(&self, range: TimeRange, out: &mut String) {
+        let storage = match self.storage.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        let mut frame_name = FrameName::new(&self.jthreads, &self.method_dict, self.frame_detail, self.demangle);
+        for (frames, samples) in storage.traces_in(range) {
+            for (idx, frame) in frames.iter().rev().enumerate() {
+                if idx > 0 {
+                    out.push(';');
+                }
+                out.push_str(frame_name.name(frame));
+            }
+            let _ = writeln!(out, " {samples}");
+        }
+    }
+
+    /// dump the samples in the range as the chrome trace json, every thread is a track of the
+    /// sampled stacks, with the GC pauses, the thread lifetimes and the lock waits.
+    pub fn dump_chrome_trace(&self, range: TimeRange, out: &mut String) {
+        let storage = match self.storage.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        let origin = storage.start_time();
+        let now = OS::nano_time();
+        let from = origin + range.from.unwrap_or(0);
+        let to = range.to.map_or(now, |to| origin + to);
+        let overlaps = |start: u64, end: u64| start <= to && end >= from;
+
+        let mut frame_name = FrameName::new(&self.jthreads, &self.method_dict, self.frame_detail, self.demangle);
+        // the stacks of the traces, root first without the thread frame.
+        let mut stacks: HashMap<u32, Vec<String>> = HashMap::new();
+        let mut threads: BTreeMap<u32, Vec<(u64, u32)>> = BTreeMap::new();
+        for sample in storage.samples() {
+            if !range.contains(sample.time.saturating_sub(origin)) {
+                continue;
+            }
+            threads.entry(sample.tid).or_default().push((sample.time, sample.trace));
+            stacks.entry(sample.trace).or_insert_with(|| {
+                storage
+                    .frames(sample.trace)
+                    .iter()
+                    .rev()
+                    .filter(|frame| frame.bci != BCI_THREADID)
+                    .map(|frame| frame_name.name(frame).to_string())
+                    .collect()
+            });
+        }
+        let spans: HashMap<u32, _> = self
+            .thread_timeline
+            .threads()
+            .into_iter()
+            .map(|span| (span.tid, span))
+            .collect();
+        let names: HashMap<u32, String> = match self.jthreads.lock() {
+            Ok(jthreads) => jthreads.iter().map(|(tid, info)| (*tid as u32, info.name.clone())).collect(),
+            Err(_) => HashMap::new(),
+        };
+        let thread_name = |tid: u32| names.get(&tid).cloned().unwrap_or_else(|| format!("thread {tid}"));
+
+        let mut trace = ChromeTrace::new(std::process::id(), origin);
+        trace.process_name("java");
+        trace.thread_name(0, "GC");
+        for (tid, samples) in threads.iter_mut() {
+            samples.sort_by_key(|(time, _)| *time);
+            let samples: Vec<(u64, &[String])> = samples
+                .iter()
+                .map(|(time, idx)| (*time, &stacks[idx][..]))
+                .collect();
+            trace.thread_name(*tid, &thread_name(*tid));
+            let end = spans.get(tid).and_then(|span| span.end);
+            let times: Vec<u64> = samples.iter().map(|(time, _)| *time).collect();
+            trace.thread_samples(*tid, &samples, self.walker_trace.sample_period(&times), end);
+        }
+        let gc_start = self.gc_timeline.start_time();
+        for pause in self.gc_timeline.pauses() {
+            let start = gc_start + pause.start;
+            if overlaps(start, start + pause.duration) {
+                trace.complete(0, "gc", &format!("GC #{}", pause.id), start, pause.duration);
+            }
+        }
+        for span in spans.values() {
+            let (start, end) = (span.start.unwrap_or(origin).max(from), span.end.unwrap_or(now).min(to));
+            if overlaps(start, end) {
+                if !threads.contains_key(&span.tid) {
+                    trace.thread_name(span.tid, &thread_name(span.tid));
+                }
+                let name = format!("thread {}", thread_name(span.tid));
+                trace.async_span(span.tid, "thread", span.tid as _, &name, start, end);
+            }
+        }
+        for (id, wait) in self.thread_timeline.lock_waits().iter().enumerate() {
+            if overlaps(wait.start, wait.start + wait.duration) {
+                let name = format!("monitor {}", wait.class);
+                trace.async_span(wait.tid, "lock", id as _, &name, wait.start, wait.start + wait.duration);
+            }
+        }
+        out.push_str(&trace.finish());
+    }
+
+    /// dump the samples in the range as the speedscope json, a sampled profile for each thread
+    /// split by the thread frame of the trace. the timed samples are weighted by the time to
+    /// the next sample of the thread, at most the thread's sample period, so the time order view works.
+    /// the samples are counted if the recent samples can't cover the whole recording.
+    pub fn dump_speedscope(&self, range: TimeRange, out: &mut String) {
+        let storage = match self.storage.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        let origin = storage.start_time();
+        let timed = !range.is_all() || (storage.dropped_samples() == 0 && storage.samples().next().is_some());
+        let mut frame_name = FrameName::new(&self.jthreads, &self.method_dict, self.frame_detail, self.demangle);
+        let mut speedscope = Speedscope::new();
+        // the profile by the thread frame, the traces without the thread frame share the profile 0.
+        let mut profiles: HashMap<u64, usize> = HashMap::new();
+        // the stack of the trace, root first without the thread frame.
+        let mut stack = |frames: &[JVMPICallFrame], speedscope: &mut Speedscope| -> (u64, Vec<usize>) {
+            let (thread, frames) = match frames.split_last() {
+                Some((last, rest)) if last.bci == BCI_THREADID => (last.method_id as u64, rest),
+                _ => (0, frames),
+            };
+            let stack = frames.iter().rev().map(|f| speedscope.frame(frame_name.name(f))).collect();
+            (thread, stack)
+        };
+        let mut thread_profile = |thread: u64, speedscope: &mut Speedscope, start: u64| {
+            *profiles.entry(thread).or_insert_with(|| {
+                let name = match self.jthreads.lock().ok().and_then(|t| t.get(&thread).map(|i| i.name.clone())) {
+                    Some(name) => name,
+                    None if thread == 0 => "[unknown thread]".to_string(),
+                    None => format!("thread {thread}"),
+                };
+                let unit = if timed { Unit::Nanoseconds } else { Unit::None };
+                speedscope.profile(&name, unit, start)
+            })
+        };
+        if timed {
+            let mut stacks: HashMap<u32, (u64, Vec<usize>)> = HashMap::new();
+            let mut threads: BTreeMap<u64, Vec<(u64, u32)>> = BTreeMap::new();
+            for sample in storage.samples() {
+                let elapsed = sample.time.saturating_sub(origin);
+                if !range.contains(elapsed) {
+                    continue;
+                }
+                let (thread, _) = stacks
+                    .entry(sample.trace)
+                    .or_insert_with(|| stack(storage.frames(sample.trace), &mut speedscope));
+                threads.entry(*thread).or_default().push((elapsed, sample.trace));
+            }
+            for (thread, mut samples) in threads {
+                samples.sort_by_key(|(time, _)| *time);
+                let profile = thread_profile(thread, &mut speedscope, samples[0].0);
+                let times: Vec<u64> = samples.iter().map(|(time, _)| *time).collect();
+                let period = self.walker_trace.sample_period(&times);
+                for (idx, (time, trace)) in samples.iter().enumerate() {
+                    let weight = samples.get(idx + 1).map_or(period, |(next, _)| (next - time).min(period));
+                    speedscope.add_sample(profile, &stacks[trace].1, weight);
+                }
+            }
+        } else {
+            let mut traces = Vec::new();
+            for (frames, samples) in storage.traces_in(range) {
+                let (thread, stack) = stack(frames, &mut speedscope);
+                traces.push((thread, stack, samples));
+            }
+            traces.sort_by_key(|(thread, _, _)| *thread);
+            for (thread, stack, samples) in traces {
+                let profile = thread_profile(thread, &mut speedscope, 0);
+                speedscope.add_sample(profile, &stack, samples);
+            }
+        }
+        speedscope.write(&format!("java {}", std::process::id()), out);
+    }
+
+    /// dump the call traces in the range as the callgrind format. the functions are named by
+    /// the method detail without the frame type, the source line is the position of the cost.
+    pub fn dump_callgrind(&self, range: TimeRange, out: &mut String) {
+        let storage = match self.storage.lock() {
+            Ok(s) => s,
+            Err(_) => return,
+        };
+        let mut frame_name = FrameName::new(&self.jthreads, &self.method_dict, FrameDetail::Method, self.demangle);
+        let mut frames_cache: HashMap<JVMPICallFrame, CgFrame> = HashMap::new();
+        let mut callgrind = Callgrind::new();
+        for (frames, samples) in storage.traces_in(range) {
+            let frames: Vec<CgFrame> = frames
+                .iter()
+                .rev()
+                .map(|frame| {
+                    frames_cache
+                        .entry(*frame)
+                        .or_insert_with(|| {
+                            let function = Self::strip_frame_type(frame, frame_name.name(frame)).to_string();
+                            let (file, line) = match frame_name.location(frame) {
+                                Some((file, line)) => (Some(file), line),
+                                None => (None, 0),
+                            };
+                            CgFrame { function, file, line }
+                        })
+                        .clone()
+                })
+                .collect();
+            callgrind.add(&frames, samples);
+        }
+        callgrind.write(std::process::id(), out);
+    }
+
+    /// the name without the frame type suffix, the interpreted and compiled frames of a method are merged.
+    fn strip_frame_type<'a>(frame: &JVMPICallFrame, name: &'a str) -> &'a str {
+        if !frame.is_java() {
+            return name;
+        }
+        name.strip_suffix(FrameType::decode(frame.bci).0.suffix()).unwrap_or(name)
+    }
+
+    /// the named stacks of the traces in the range, root first without the thread frame and
+    /// the frame types.
+    fn named_traces(&self, range: TimeRange) -> Vec<(Vec<String>, u64)> {
+        let storage = match self.storage.lock() {
+            Ok(s) => s,
+            Err(_) => return Vec::new(),
+        };
+        let mut frame_name = FrameName::new(&self.jthreads, &self.method_dict, self.frame_detail, self.demangle);
+        let mut names: HashMap<JVMPICallFrame, String> = HashMap::new();
+        storage
+            .traces_in(range)
+            .into_iter()
+            .map(|(frames, samples)| {
+                let stack = frames
+                    .iter()
+                    .rev()
+                    .filter(|frame| frame.bci != BCI_THREADID)
+                    .map(|frame| names.entry(*frame).or_insert_with(|| Self::strip_frame_type(frame, frame_name.name(frame)).to_string()).clone())
+                    .collect();
+                (stack, samples)
+            })
+            .collect()
+    }
+
+    /// the flat profile of the top frames by the self or the total samples.
+    pub fn dump_top(&self, args: ReportArgs, out: &mut String) {
+        let mut flat = FlatProfile::new();
+        for (stack, samples) in self.named_traces(args.range) {
+            flat.add(&stack, samples);
+        }
+        flat.report(args.limit, args.sort_total, out);
+    }
+
+    /// the forward call tree from the roots, or the reverse tree from the top frames to the callers.
+    pub fn dump_call_tree(&self, args: ReportArgs, reverse: bool, out: &mut String) {
+        let mut tree = CallTree::new();
+        for (stack, samples) in self.named_traces(args.range) {
+            if reverse {
+                tree.add(stack.iter().rev(), samples);
+            } else {
+                tree.add(&stack, samples);
+            }
+        }
+        tree.report(args.limit, args.min_percent, out);
+    }
+
+    /// break down the samples of the top n methods by the line and the bci.
+    pub fn dump_hot_lines(&self, top: usize, range: TimeRange, out: &mut String) {
+        let mut histogram = BciHistogram::new();
+        if let Ok(storage) = self.storage.lock() {
+            for (frames, samples) in storage.traces_in(range) {
+                histogram.add(frames, samples);
+            }
+        }
+        if let Ok(mut dict) = self.method_dict.lock() {
+            let jvmti = get_vm().jvmti();
+            for method in histogram.top(top) {
+                dict.intern(jvmti, method);
+            }
+            histogram.report(top, &dict, out);
+        }
     }
 
     pub fn get_call_trace(&mut self, ucontext: *mut libc::c_void) {
+        let time = OS::nano_time();
         let tid = OS::thread_id();
         let lock_idx = self.get_lock_index(tid) as usize;
         self.locks.get(lock_idx).map(|l| l.try_lock());
         
+        self.total_samples.fetch_add(1, Ordering::Relaxed);
         let mut java_ctx = StackContext::new();
         unsafe {
+            if self.pc_histogram.is_enabled() {
+                let mut frame = StackFrame::new(ucontext as _);
+                self.pc_histogram.record(*frame.pc() as _);
+            }
             let frame_buff = self
                 .calltrace_buffer
                 .get_mut(lock_idx)
                 .expect("get idx calltrace buffer fail");
             let frame_buf_ptr = frame_buff.as_mut_ptr();
-            let num_frames = self.get_native_trace(ucontext, frame_buf_ptr, &mut java_ctx);
-            let num_frames = num_frames + self.get_java_async_trace(ucontext, frame_buf_ptr.add(num_frames));
-            let num_frames = num_frames + self.make_frame(frame_buf_ptr.add(num_frames), BCI_THREADID, tid as _);
-            self.walk_frames(frame_buf_ptr, num_frames);
+            let mut num_frames = self.get_native_trace(ucontext, frame_buf_ptr, &mut java_ctx);
+            if !java_ctx.pc.is_null() {
+                if let Some(frame) = self.find_code_blob_frame(java_ctx.pc as _) {
+                    *frame_buf_ptr.add(num_frames) = frame;
+                    num_frames += 1;
+                }
+            }
+            // the thread isn't java thread, only the GC and VM threads are counted into the GC.
+            let mut in_gc = self.gc_timeline.gc_active() && self.gc_timeline.is_gc_thread();
+            if let Some(jni) = get_vm_mut().get_jni_env() {
+                let java_frames = self.get_java_async_trace(&jni, ucontext, frame_buf_ptr.add(num_frames));
+                if java_frames > 0 {
+                    let java_start = num_frames;
+                    num_frames += java_frames as usize;
+                    let java_trace = std::slice::from_raw_parts_mut(frame_buf_ptr.add(java_start), java_frames as _);
+                    self.fill_frame_types(&java_ctx, java_trace);
+                } else if java_frames < 0 {
+                    self.record_asgct_failure(java_frames);
+                    let walked = if java_frames != ASGCTFAIL_TICKS_GCACTIVE {
+                        self.walk_java_frames(&jni, &java_ctx, frame_buf_ptr.add(num_frames))
+                    } else {
+                        0
+                    };
+                    // keep the failed walk as the stack end with the error frame.
+                    if walked > 0 {
+                        num_frames += walked;
+                    } else {
+                        num_frames += self.make_frame(frame_buf_ptr.add(num_frames), BCI_ERROR, java_frames as isize as _);
+                    }
+                }
+                in_gc = java_frames == ASGCTFAIL_TICKS_GCACTIVE;
+            }
+            if self.gc_timeline.record_sample(in_gc) {
+                num_frames += self.make_frame(frame_buf_ptr.add(num_frames), BCI_GC, ptr::null_mut());
+            }
+            num_frames += self.make_frame(frame_buf_ptr.add(num_frames), BCI_THREADID, tid as _);
+            let trace = JVMPICallTrace {
+                env: ptr::null_mut(),
+                num_frames: num_frames as _,
+                frames: frame_buf_ptr,
+            };
+            self.queue.push(&trace, time, tid);
         }
         self.locks.get(lock_idx).map(|l| l.unlock());
     }
@@ -204,28 +938,82 @@ impl Profiler {
     }
 
     /// get the java async trace, call the AsyncGetCallTrace
+    /// return the frame number, or the ASGCTFAIL_TICKS code if the walk fail.
     unsafe fn get_java_async_trace(
         &mut self, 
+        jni: &JNIEnv,
         ucontext: *mut libc::c_void, 
         frame_buf_ptr: *mut JVMPICallFrame
-    ) -> usize {
+    ) -> i32 {
         let vm = get_vm_mut();
-        let jni = vm.get_jni_env();
-        if jni.is_none() {
-            //Not java thread.
-            return 0;
-        }
         let mut curr_frame = StackFrame::new(ucontext as _);
         // when exit the block will restore the pc, sp, fp by drop
         let _saved_frame = curr_frame.save_frame(true);
-        let jni = jni.unwrap();
         let mut call_trace = JVMPICallTrace::new(jni.inner(), frame_buf_ptr);
         //call AsyncGetCallTrace.
         (vm.asgc())(&mut call_trace as *mut _, self.max_frames as _, ucontext);
-        if call_trace.num_frames > 0 {
-            return call_trace.num_frames as _;
+        call_trace.num_frames
+    }
+
+    /// walk the java frames by the VMStructs when the AsyncGetCallTrace fails,
+    /// start from the java pc found by the native walk, or the last java frame anchor
+    /// if the thread is in the native or VM code.
+    unsafe fn walk_java_frames(
+        &self,
+        jni: &JNIEnv,
+        java_ctx: &StackContext,
+        frame_buf_ptr: *mut JVMPICallFrame,
+    ) -> usize {
+        let vm = get_vm();
+        let walker = vm.java_walker();
+        if !walker.available() || vm.thread_env_offset() < 0 {
+            return 0;
+        }
+        let frames = std::slice::from_raw_parts_mut(frame_buf_ptr, self.max_frames);
+        if !java_ctx.pc.is_null() {
+            return walker.walk(java_ctx, frames);
+        }
+        let thread = VMThread::from_jni_env(jni);
+        match walker.last_java_frame(&thread) {
+            Some(ctx) => walker.walk(&ctx, frames),
+            None => 0,
+        }
+    }
+
+    #[inline(always)]
+    fn record_asgct_failure(&self, code: i32) {
+        if let Some(counter) = self.asgct_failures.get(code.unsigned_abs() as usize) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// the status of the profiler, include the AsyncGetCallTrace failures.
+    pub fn status(&self, out: &mut String) {
+        let samples = self.total_samples.load(Ordering::Relaxed);
+        let failures: u64 = self
+            .asgct_failures
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .sum();
+        let percent = |n: u64| if samples == 0 { 0f64 } else { n as f64 * 100f64 / samples as f64 };
+        let _ = writeln!(out, "running: {}", self.running.load(Ordering::Acquire));
+        let _ = writeln!(out, "samples: {samples}");
+        if let Ok(storage) = self.storage.lock() {
+            let elapsed = OS::nano_time().saturating_sub(storage.start_time());
+            let _ = writeln!(
+                out, "timed samples: {} in {:.3} ms (dropped {})",
+                storage.samples().count(), elapsed as f64 / 1e6, storage.dropped_samples()
+            );
+        }
+        let _ = writeln!(out, "lock waits: {}", self.thread_timeline.total_lock_waits());
+        let _ = writeln!(out, "kernel symbols: {}", SymbolParser::instance().have_kernel_symbols());
+        let _ = writeln!(out, "asgct failures: {failures} ({:.2}%)", percent(failures));
+        for (counter, name) in self.asgct_failures.iter().zip(ASGCTFAIL_NAMES) {
+            let n = counter.load(Ordering::Relaxed);
+            if n > 0 {
+                let _ = writeln!(out, "  {name:<24} {n:>10} ({:.2}%)", percent(n));
+            }
         }
-        0
     }
 
     fn get_lock_index(&self, tid: u32) -> u32 {
@@ -240,31 +1028,59 @@ impl Profiler {
         call_chan: &[*const ()], 
         frame_buf_ptr: *mut JVMPICallFrame
     ) -> usize {
-        let mut prev_call = ptr::null();
         let mut num_frames = 0;
-        for cc in call_chan {
-            let nm = self.find_native_method(*cc as _);
-            if let Some(nm) = nm {
-                prev_call = nm as _;
+        // keep the pc for the line detail, it's resolved to the source line at dump time.
+        let native_lines = self.frame_detail == FrameDetail::Line;
+        for (i, cc) in call_chan.iter().enumerate() {
+            // the inlined jit frames can fill the native part, the rest of the buffer is
+            // reserved for the java and the special frames.
+            if num_frames >= MAX_NATIVE_FRAMES {
+                break;
+            }
+            // the library blobs are never moved, the stubs are sorted at the add so only
+            // their names are kept.
+            let frame = match self.code_caches.lookup(*cc as _) {
+                Some(CodeLookup::Library(lib)) => lib
+                    .binary_search(*cc as _)
+                    .map(|nm| (BCI_NATIVE_FRAME, nm as *const CodeBlob as jmethodID)),
+                Some(CodeLookup::RuntimeStubs) => self
+                    .find_runtime_stub(*cc as _)
+                    .map(|stub| (BCI_CODE_BLOB, stub.c_name() as _)),
+                None => None,
+            };
+            if let Some((bci, method_id)) = frame {
                 let frame_ptr = frame_buf_ptr.add(num_frames);
                 num_frames += 1;
-                (*frame_ptr).bci = BCI_NATIVE_FRAME;
-                (*frame_ptr).method_id = nm as *const _  as _;
+                if native_lines {
+                    // the caller pc is the return address, step back into the call instruction.
+                    let pc = if i > 0 { (*cc as *const i8).wrapping_sub(1) } else { *cc as *const i8 };
+                    (*frame_ptr).bci = BCI_NATIVE_PC;
+                    (*frame_ptr).method_id = pc as _;
+                } else {
+                    (*frame_ptr).bci = bci;
+                    (*frame_ptr).method_id = method_id;
+                }
+            } else {
+                // the walk passed the compiled java code before the code heap bounds are known.
+                let frames = std::slice::from_raw_parts_mut(frame_buf_ptr.add(num_frames), MAX_NATIVE_FRAMES - num_frames);
+                num_frames += self.find_jit_frames(*cc as _, frames);
             }
         }
         num_frames
     }
 
-    unsafe fn walk_frames(&self, frame_buf_ptr: *mut JVMPICallFrame, nums: usize) {
-        let mut frame_name = FrameName::new(&self.jthreads);
-        for idx in 0..nums {
-            let frame = &(*frame_buf_ptr.add(idx));
-            let name = frame_name.name(frame);
-            if frame.bci != BCI_THREADID {
-                println!("{name}");
+    /// classify the top java frames by the code blob of the pc where the native walk stopped,
+    /// the callers by the jit code of their methods.
+    unsafe fn fill_frame_types(&self, java_ctx: &StackContext, frames: &mut [JVMPICallFrame]) {
+        let mut classified = 0;
+        if !java_ctx.pc.is_null() {
+            if let Some(nmethod) = get_vm().code_heap().find_nmethod(java_ctx.pc as _) {
+                classified = FrameType::fill_frame_types(frames, &nmethod);
             }
         }
-        println!("----------------------------------------------");
+        if let Some(_guard) = self.jit_lock.try_lock_with_guard() {
+            self.jit_code.fill_frame_types(&mut frames[classified..]);
+        }
     }
 
     pub unsafe fn update_thread_info(&mut self, jvmti: JvmtiEnv, jni: JNIEnv, thread: jthread) {
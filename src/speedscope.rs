@@ -0,0 +1,154 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::json;
+
+/// the unit of the sample weights.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// the weights are the sample counts.
+    None,
+    Nanoseconds,
+}
+
+impl Unit {
+    fn name(&self) -> &'static str {
+        match self {
+            Unit::None => "none",
+            Unit::Nanoseconds => "nanoseconds",
+        }
+    }
+}
+
+struct Profile {
+    name: String,
+    unit: Unit,
+    start: u64,
+    /// the stacks of the frame index, root first.
+    samples: Vec<Vec<usize>>,
+    weights: Vec<u64>,
+}
+
+/// Write the speedscope file format, see https://www.speedscope.app/file-format-schema.json.
+/// Every profile is the sampled profile of a thread, the frames are shared by the profiles.
+/// The samples are kept in the added order, which is the time order if they are timed.
+pub struct Speedscope {
+    frames: Vec<String>,
+    index: HashMap<String, usize>,
+    profiles: Vec<Profile>,
+}
+
+impl Speedscope {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            index: HashMap::new(),
+            profiles: Vec::new(),
+        }
+    }
+
+    /// the index of the frame in the shared frames.
+    pub fn frame(&mut self, name: &str) -> usize {
+        if let Some(idx) = self.index.get(name) {
+            return *idx;
+        }
+        let idx = self.frames.len();
+        self.frames.push(name.to_string());
+        self.index.insert(name.to_string(), idx);
+        idx
+    }
+
+    /// add the profile starts at the start value, return its index.
+    pub fn profile(&mut self, name: &str, unit: Unit, start: u64) -> usize {
+        self.profiles.push(Profile {
+            name: name.to_string(),
+            unit,
+            start,
+            samples: Vec::new(),
+            weights: Vec::new(),
+        });
+        self.profiles.len() - 1
+    }
+
+    pub fn add_sample(&mut self, profile: usize, stack: &[usize], weight: u64) {
+        let profile = &mut self.profiles[profile];
+        profile.samples.push(stack.to_vec());
+        profile.weights.push(weight);
+    }
+
+    pub fn write(&self, name: &str, out: &mut String) {
+        out.push_str("{\"$schema\":\"https://www.speedscope.app/file-format-schema.json\",\"name\":");
+        json::write_str(out, name);
+        out.push_str(",\"exporter\":\"simple-jprofiler\",\"activeProfileIndex\":0,\n\"shared\":{\"frames\":[");
+        for (idx, frame) in self.frames.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            out.push_str("\n{\"name\":");
+            json::write_str(out, frame);
+            out.push('}');
+        }
+        out.push_str("]},\n\"profiles\":[");
+        for (idx, profile) in self.profiles.iter().enumerate() {
+            if idx > 0 {
+                out.push(',');
+            }
+            let end = profile.start + profile.weights.iter().sum::<u64>();
+            out.push_str("\n{\"type\":\"sampled\",\"name\":");
+            json::write_str(out, &profile.name);
+            let _ = write!(
+                out, ",\"unit\":\"{}\",\"startValue\":{},\"endValue\":{end},\n\"samples\":[",
+                profile.unit.name(), profile.start
+            );
+            for (i, stack) in profile.samples.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push('[');
+                for (j, frame) in stack.iter().enumerate() {
+                    if j > 0 {
+                        out.push(',');
+                    }
+                    let _ = write!(out, "{frame}");
+                }
+                out.push(']');
+            }
+            out.push_str("],\n\"weights\":[");
+            for (i, weight) in profile.weights.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let _ = write!(out, "{weight}");
+            }
+            out.push_str("]}");
+        }
+        out.push_str("]}\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let mut speedscope = Speedscope::new();
+        let main = speedscope.frame("Main.main([Ljava/lang/String;)V");
+        let foo = speedscope.frame("Main.foo()V");
+        assert_eq!(speedscope.frame("Main.foo()V"), foo);
+        let worker = speedscope.profile("worker", Unit::Nanoseconds, 100);
+        speedscope.add_sample(worker, &[main, foo], 10);
+        speedscope.add_sample(worker, &[main], 5);
+        let other = speedscope.profile("other", Unit::None, 0);
+        speedscope.add_sample(other, &[main], 3);
+
+        let mut out = String::new();
+        speedscope.write("test", &mut out);
+        assert!(out.contains(r#""frames":[
+{"name":"Main.main([Ljava/lang/String;)V"},
+{"name":"Main.foo()V"}]}"#));
+        assert!(out.contains(r#""name":"worker","unit":"nanoseconds","startValue":100,"endValue":115,
+"samples":[[0,1],[0]],
+"weights":[10,5]}"#));
+        assert!(out.contains(r#""unit":"none","startValue":0,"endValue":3,"#));
+    }
+}
@@ -2,7 +2,11 @@ use std::{mem, ptr};
 
 use libc::uintptr_t;
 
-use crate::{get_vm, stack_frame::StackFrame};
+use crate::{
+    dwarf::{DEFAULT_FRAME, DWARF_SUPPORTED, DW_REG_FP, DW_REG_PLT, DW_REG_SP, DW_SAME_FP},
+    get_vm,
+    stack_frame::StackFrame,
+};
 
 const MAX_FRAME_SIZE: usize = 0x40000;
 const MAX_WALK_SIZE: usize = 0x10000;
@@ -34,10 +38,76 @@ impl StackContext {
 pub struct StackWalker;
 
 impl StackWalker {
+    /// walk the native frames with the dwarf frame table of the libraries,
+    /// the frame pointer is used for the code without the dwarf info.
     pub unsafe fn walk_frame<'a>(
         ucontext: *const (),
         call_chan: &'a mut [*const ()],
         java_ctx: &mut StackContext,
+    ) -> &'a [*const ()] {
+        if DWARF_SUPPORTED {
+            Self::walk_dwarf(ucontext, call_chan, java_ctx)
+        } else {
+            Self::walk_fp(ucontext, call_chan, java_ctx)
+        }
+    }
+
+    pub unsafe fn walk_dwarf<'a>(
+        ucontext: *const (),
+        call_chan: &'a mut [*const ()],
+        java_ctx: &mut StackContext,
+    ) -> &'a [*const ()] {
+        let sp = 0;
+        let bottom = (&sp as *const _ as uintptr_t) + MAX_WALK_SIZE;
+        let mut frame = StackFrame::new(ucontext as _);
+        let mut pc = *frame.pc() as *const ();
+        let mut fp = *frame.fp() as uintptr_t;
+        let mut sp = *frame.sp() as uintptr_t;
+        let mut deep = 0;
+        let vm = get_vm();
+        while deep < call_chan.len() {
+            if vm.code_heap().code_contains(pc as _) {
+                java_ctx.set(pc, sp, fp);
+                break;
+            }
+            call_chan[deep] = pc;
+            deep += 1;
+            let prev_sp = sp;
+            let f = match vm.profiler().find_lib_by_address(pc as _) {
+                Some(cc) => cc.find_frame_desc(pc as _),
+                None => &DEFAULT_FRAME,
+            };
+            let cfa_off = f.cfa_off() as uintptr_t;
+            sp = match f.cfa_reg() {
+                DW_REG_SP => sp.wrapping_add(cfa_off),
+                DW_REG_FP => fp.wrapping_add(cfa_off),
+                // the PLT entry pushes one more slot after the 11th byte.
+                DW_REG_PLT if (pc as uintptr_t & 15) >= 11 => sp.wrapping_add(cfa_off * 2),
+                DW_REG_PLT => sp.wrapping_add(cfa_off),
+                _ => break,
+            };
+            if sp < prev_sp || sp >= prev_sp + MAX_FRAME_SIZE || sp >= bottom {
+                break;
+            }
+            if sp & (mem::size_of::<uintptr_t>() - 1) != 0 {
+                break;
+            }
+            if f.fp_off != DW_SAME_FP && f.fp_off.unsigned_abs() < MAX_FRAME_SIZE as u32 {
+                fp = *(sp.wrapping_add_signed(f.fp_off as isize) as *const uintptr_t);
+            }
+            // the return address is just below the cfa.
+            pc = *(sp as *const *const ()).sub(1);
+            if pc < MIN_VALID_PC as _ || pc > (-MIN_VALID_PC) as _ {
+                break;
+            }
+        }
+        &call_chan[0..deep]
+    }
+
+    pub unsafe fn walk_fp<'a>(
+        ucontext: *const (),
+        call_chan: &'a mut [*const ()],
+        java_ctx: &mut StackContext,
     ) -> &'a [*const ()] {
         let sp = 0;
         let bottom = (&sp as *const _ as uintptr_t) + MAX_WALK_SIZE;
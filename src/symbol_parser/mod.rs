@@ -9,7 +9,7 @@ use symbol_linux::SymbolParserImpl;
 
 use std::sync::{Mutex, Once};
 
-use crate::code_cache::CodeCache;
+use crate::code_cache::CodeCacheArray;
 
 static INSTANCE_ONCE: Once = Once::new();
 
@@ -38,8 +38,13 @@ impl SymbolParser {
         unsafe { INSTANCE.as_mut().unwrap() }
     }
 
-    pub fn parse_libraries(&mut self, code_caches: &mut Vec<CodeCache>, parse_kernel: bool) {
+    /// parse the new libraries, the kernel symbols are parsed once if parse_kernel.
+    pub fn parse_libraries(&mut self, code_caches: &CodeCacheArray, parse_kernel: bool) {
         let _lock = self.mutex.lock();
-        self.symbol_impl.parse_libraries(code_caches, parse_kernel);
+        if parse_kernel && !self.have_kernel_symbols {
+            self.have_kernel_symbols = self.symbol_impl.parse_kernel_symbols(code_caches);
+        }
+        self.symbol_impl.parse_libraries(code_caches);
+        code_caches.update_index();
     }
 }
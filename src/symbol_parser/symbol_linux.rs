@@ -1,11 +1,12 @@
 use std::{
-    collections::HashSet,
+    collections::{hash_map::Entry, HashMap, HashSet},
     fs::{self, OpenOptions},
-    io::{BufRead, BufReader, Seek, SeekFrom},
-    ptr::{self, null_mut}, os::fd::AsRawFd, ffi::CStr, mem,
+    io::{BufRead, BufReader, Read},
+    ptr::{self, null_mut}, os::fd::AsRawFd, ffi::{CStr, CString}, mem, slice,
 };
 
-use crate::{code_cache::CodeCache, profiler::MAX_CODE_CACHE_ARRAY, log_warn, vec_append_slice};
+use crate::{c_str, code_cache::{CodeCache, CodeCacheArray}, log_warn, vec_append_slice};
+use crate::dwarf::{DwarfParser, DWARF_SUPPORTED};
 
 const SHN_UNDEF: u8 = 0;
 const ET_EXEC: u16 = 2;
@@ -43,6 +44,10 @@ const SHT_RELA: u32 = 4;
 const SHT_NOTE: u32 = 4;
 const SHT_REL: u32 = 9;
 const SHT_DYNSYM: u32 = 9;
+const SHT_NOBITS: u32 = 8;
+
+const ELFCOMPRESS_ZLIB: u32 = 1;
+const ELFCOMPRESS_ZSTD: u32 = 2;
 
 #[cfg(any(target_arch = "x86_64", target_arch = "i386"))]
 mod x86_64_i386 {
@@ -119,6 +124,17 @@ mod target_64 {
         pub d_un: UnnamedDyn64,
     }
 
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    #[allow(non_camel_case_types)]
+    pub struct Elf64_Chdr {
+        pub ch_type: libc::Elf64_Word,
+        pub ch_reserved: libc::Elf64_Word,
+        pub ch_size: libc::Elf64_Xword,
+        pub ch_addralign: libc::Elf64_Xword,
+    }
+
+    pub const SHF_COMPRESSED: libc::Elf64_Xword = 0x800;
     pub const ELFCLASS_SUPPORTED: u8 = libc::ELFCLASS64;
     pub type ElfHeader = libc::Elf64_Ehdr;
     pub type ElfSection = libc::Elf64_Shdr;
@@ -127,6 +143,7 @@ mod target_64 {
     pub type ElfSymbol = libc::Elf64_Sym;
     pub type ElfRelocation = Elf64_Rel;
     pub type ElfDyn = Elf64_Dyn;
+    pub type ElfChdr = Elf64_Chdr;
     pub const ELF_R_SYM_BIT: u32 = 32;
 }
 
@@ -172,6 +189,16 @@ mod target_32 {
         d_un: UnnamedDyn,
     }
 
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    #[allow(non_camel_case_types)]
+    pub struct Elf32_Chdr {
+        pub ch_type: libc::Elf32_Word,
+        pub ch_size: libc::Elf32_Word,
+        pub ch_addralign: libc::Elf32_Word,
+    }
+
+    pub const SHF_COMPRESSED: libc::Elf32_Word = 0x800;
     pub const ELFCLASS_SUPPORTED: u8 = libc::ELFCLASS32;
     pub type ElfHeader = libc::Elf32_Ehdr;
     pub type ElfSection = libc::Elf32_Shdr;
@@ -180,6 +207,7 @@ mod target_32 {
     pub type ElfSymbol = libc::Elf32_Sym;
     pub type ElfRelocation = Elf32_Rel;
     pub type ElfDyn = libc::Elf32_Dyn;
+    pub type ElfChdr = Elf32_Chdr;
     pub const ELF_R_SYM_BIT: u32 = 8;
 }
 
@@ -296,20 +324,56 @@ impl<'a> MemoryMapDesc<'a> {
 }
 
 pub(crate) struct SymbolParserImpl {
-    parsed_library: HashSet<u64>,
-    parsed_inode: HashSet<u64>,
+    /// the slot of the code cache by the mapping base and inode.
+    parsed_library: HashMap<(u64, u64), usize>,
+    /// the mapping base of the parsed file by the dev and inode.
+    parsed_inode: HashMap<u64, u64>,
 }
 
 impl SymbolParserImpl {
     pub fn new() -> Self {
         Self {
-            parsed_library: HashSet::new(),
-            parsed_inode: HashSet::new(),
+            parsed_library: HashMap::new(),
+            parsed_inode: HashMap::new(),
+        }
+    }
+
+    /// parse the kernel functions in the kallsyms into the "[kernel]" code cache, so the
+    /// kernel range is indexed like the libraries. the addresses are all zero if they
+    /// are hidden by the kptr_restrict.
+    pub fn parse_kernel_symbols(&mut self, code_caches: &CodeCacheArray) -> bool {
+        let kallsyms = match fs::File::open("/proc/kallsyms") {
+            Ok(f) => BufReader::new(f),
+            Err(_) => return false,
+        };
+        let mut cc = unsafe { CodeCache::new(c_str!("[kernel]"), code_caches.count() as _) };
+        for line in kallsyms.lines().map_while(Result::ok) {
+            // "ffffffff81000000 T _stext", the module symbols have the "[module]" after.
+            let mut fields = line.split_whitespace();
+            let (addr, kind, name) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(addr), Some(kind), Some(name)) => (addr, kind, name),
+                _ => continue,
+            };
+            if !matches!(kind, "t" | "T" | "w" | "W") {
+                continue;
+            }
+            let addr = match u64::from_str_radix(addr, 16) {
+                Ok(addr) if addr != 0 => addr,
+                _ => continue,
+            };
+            if let Ok(name) = CString::new(name) {
+                unsafe { cc.add(addr as _, 0, name.as_ptr(), true) };
+            }
         }
+        if cc.code_blobs().is_empty() {
+            return false;
+        }
+        cc.sort();
+        code_caches.add(cc)
     }
 
-    /// parse the libraries in maps file.
-    pub fn parse_libraries(&mut self, code_caches: &mut Vec<CodeCache>, _parse_kernel: bool) {
+    /// parse the libraries in maps file, and remove the code caches of the unmapped ones.
+    pub fn parse_libraries(&mut self, code_caches: &CodeCacheArray) {
         let mut map_file = match fs::OpenOptions::new().read(true).open("/proc/self/maps") {
             Ok(f) => BufReader::new(f),
             Err(_) => return,
@@ -317,43 +381,64 @@ impl SymbolParserImpl {
         let mut line = String::new();
         let mut image_end: *const i8 = ptr::null();
         let mut last_readable_base: *const i8 = ptr::null();
-
-        while let Ok(n) = map_file.read_line(&mut line) {
-            if n == 0 {
-                break;
+        // the file offset and inode of the mapping at the last_readable_base.
+        let mut last_readable_offs = 0;
+        let mut last_readable_inode = 0;
+        // the executable mappings seen, unknown if the array is full.
+        let mut mapped = HashSet::new();
+        let mut complete = true;
+
+        loop {
+            // the skipped lines must be cleared too, read_line appends to the line.
+            line.clear();
+            match map_file.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
             }
-            let desc = MemoryMapDesc::parse(&line.as_bytes());
-            if !desc.is_readable() || desc.is_empty_file() {
+            let desc = MemoryMapDesc::parse(line.trim_end().as_bytes());
+            if desc.perm.len() < 3 || !desc.is_readable() || desc.is_empty_file() {
                 continue;
             }
             let mut image_base = desc.addr();
-            if image_base == image_end {
+            // a gap to the previous mapping is the start of a new image.
+            if image_base != image_end {
                 last_readable_base = image_base;
+                last_readable_offs = desc.offs();
+                last_readable_inode = desc.inode();
             }
             image_end = desc.end();
 
             if desc.is_executable() {
+                let key = (image_base as u64, desc.inode());
+                mapped.insert(key);
                 // if already parsed the file, don't parse again.
-                if !self.parsed_library.insert(image_base as _) {
+                if self.parsed_library.contains_key(&key) {
                     continue;
                 }
 
-                let array_len = code_caches.len();
-                if array_len >= MAX_CODE_CACHE_ARRAY as _ {
+                if code_caches.is_full() {
+                    complete = false;
                     break;
                 }
-                let mut cc = CodeCache::new_with_address_range(
-                    desc.file.as_ptr() as _,
-                    array_len as _,
-                    image_base,
-                    image_end,
-                );
+                let array_len = code_caches.count();
+                self.parsed_library.insert(key, array_len);
+                let name = CString::new(desc.file).unwrap_or_default();
+                let mut cc = unsafe {
+                    CodeCache::new_with_address_range(name.as_ptr(), array_len as _, image_base, image_end)
+                };
                 let inode = desc.inode();
                 unsafe {
                     if inode != 0 {
                         // Do not parse the same executable twice, e.g. on Alpine Linux
-                        if self.parsed_inode.insert(desc.dev() << 32 | inode) {
-                            image_base =  image_base.offset(-(desc.offs() as isize));
+                        if let Entry::Vacant(entry) = self.parsed_inode.entry(desc.dev() << 32 | inode) {
+                            entry.insert(key.0);
+                            // the segment vaddr can differ from the file offset, e.g. linked by lld,
+                            // prefer the mapping of the elf header.
+                            image_base = if last_readable_offs == 0 && last_readable_inode == inode {
+                                last_readable_base
+                            } else {
+                                image_base.offset(-(desc.offs() as isize))
+                            };
                             if image_base >= last_readable_base {
                                 ElfParser::parse_program_headers(&mut cc, image_base, image_end);
                             }
@@ -364,10 +449,25 @@ impl SymbolParserImpl {
                     }
                 }
                 cc.sort();
-                code_caches.push(cc);
+                code_caches.add(cc);
             }
-            line.truncate(0);
         }
+        if complete {
+            self.remove_unmapped(code_caches, &mapped);
+        }
+    }
+
+    /// remove the code caches of the dlclosed libraries, so the lookups don't resolve
+    /// through the stale ranges. a library mapped again is parsed again.
+    fn remove_unmapped(&mut self, code_caches: &CodeCacheArray, mapped: &HashSet<(u64, u64)>) {
+        self.parsed_library.retain(|key, idx| {
+            if mapped.contains(key) {
+                return true;
+            }
+            code_caches.remove(*idx);
+            false
+        });
+        self.parsed_inode.retain(|_, base| self.parsed_library.keys().any(|(b, _)| b == base));
     }
 }
 
@@ -398,10 +498,11 @@ impl<'a, 'b> ElfParser<'a, 'b> {
     }
 
     unsafe fn find_section(&mut self, typ: u32, sec_name: &[u8]) -> *const ElfSection  {
-        let strtab = self.at_section(self.section((*self.header).e_shstrndx as _));
+        let strsec = self.section((*self.header).e_shstrndx as _);
+        let strtab = self.at_section(strsec);
         for i in 0..(*self.header).e_shnum {
             let sec = self.section(i as _);
-            if typ == (*sec).sh_type && (*sec).sh_name != 0 {
+            if typ == (*sec).sh_type && (*sec).sh_name != 0 && ((*sec).sh_name as u64) < (*strsec).sh_size {
                 let name_ptr = strtab.offset((*sec).sh_name as _) as *const i8;
                 let name = CStr::from_ptr(name_ptr).to_bytes();
                 if name == sec_name {
@@ -426,14 +527,37 @@ impl<'a, 'b> ElfParser<'a, 'b> {
             && elf_header.e_shstrndx != SHN_UNDEF as _
     }
 
+    /// the section headers and the section data are in the image of the len, and the section
+    /// names are terminated. the elf decompressed from the .gnu_debugdata isn't trusted.
+    unsafe fn sections_in(&self, len: usize) -> bool {
+        let header = &*self.header;
+        let in_image = |offset: u64, size: u64| offset.checked_add(size).is_some_and(|end| end <= len as u64);
+        let headers_size = header.e_shnum as u64 * header.e_shentsize as u64;
+        if (header.e_shentsize as usize) < mem::size_of::<ElfSection>()
+            || !in_image(header.e_shoff as _, headers_size)
+            || header.e_shstrndx >= header.e_shnum
+        {
+            return false;
+        }
+        let sections_in = (0..header.e_shnum).all(|i| {
+            let sec = &*self.section(i as _);
+            sec.sh_type == SHT_NOBITS || in_image(sec.sh_offset as _, sec.sh_size as _)
+        });
+        let names = &*self.section(header.e_shstrndx as _);
+        sections_in && names.sh_size > 0 && *self.at_section(names).add(names.sh_size as usize - 1) == 0
+    }
+
     unsafe fn parse_file(cc: &mut CodeCache, base: *const i8, file_n: &str, debug: bool) -> bool {
-        let mut file = match OpenOptions::new()
+        let file = match OpenOptions::new()
             .read(true)
             .open(file_n) {
             Ok(f) => f,
             Err(_) => return false,
         };
-        let file_len = file.seek(SeekFrom::Start(0)).expect("seek error");
+        let file_len = match file.metadata() {
+            Ok(meta) if meta.len() > 0 => meta.len(),
+            _ => return false,
+        };
         let fd = file.as_raw_fd();
         let addr = libc::mmap(null_mut(), file_len as _, libc::PROT_READ, libc::MAP_PRIVATE, fd, 0);
         drop(file);
@@ -469,6 +593,12 @@ impl<'a, 'b> ElfParser<'a, 'b> {
     }
 
     unsafe fn load_symbols(&mut self, debug: bool) {
+        // the line table is read lazily at dump time, only remember the file.
+        if !self.find_section(SHT_PROGBITS, b".debug_line").is_null() {
+            if let Some(file_name) = self.file_name {
+                self.cc.set_debug_file(std::str::from_utf8_unchecked(file_name));
+            }
+        }
         //look debug symbol in original so.
         let section = self.find_section(SHT_SYMTAB, b".symtab");
         if !section.is_null() {
@@ -488,6 +618,42 @@ impl<'a, 'b> ElfParser<'a, 'b> {
         if !section.is_null() {
             self.load_symbol_table(section);
         }
+        // the MiniDebugInfo only keeps the symbols missed in the .dynsym.
+        self.load_symbols_using_debugdata();
+    }
+
+    /// the .gnu_debugdata is a xz compressed elf with the .symtab, like the binaries of Fedora.
+    unsafe fn load_symbols_using_debugdata(&mut self) -> bool {
+        let section = self.find_section(SHT_PROGBITS, b".gnu_debugdata");
+        if section.is_null() {
+            return false;
+        }
+        let data = slice::from_raw_parts(self.at_section(section) as *const u8, (*section).sh_size as _);
+        let mut elf = Vec::new();
+        if lzma_rs::xz_decompress(&mut &data[..], &mut elf).is_err() || elf.len() < mem::size_of::<ElfHeader>() {
+            return false;
+        }
+        let mut parser = ElfParser::new(self.cc, self.base, elf.as_ptr() as _, self.file_name);
+        if !parser.valid_header() || !parser.sections_in(elf.len()) {
+            return false;
+        }
+        let symtab = parser.find_section(SHT_SYMTAB, b".symtab");
+        if symtab.is_null() {
+            return false;
+        }
+        parser.load_symbol_table(symtab);
+        true
+    }
+
+    /// the data of the section, the SHF_COMPRESSED section is decompressed into the buffer.
+    unsafe fn section_data(&self, sec: *const ElfSection, buf: &mut Vec<u8>) -> Option<*const u8> {
+        let data = self.at_section(sec) as *const u8;
+        if (*sec).sh_flags & SHF_COMPRESSED == 0 {
+            return Some(data);
+        }
+        let compressed = slice::from_raw_parts(data, (*sec).sh_size as _);
+        decompress_section(compressed, buf)?;
+        Some(buf.as_ptr())
     }
 
     unsafe fn load_symbols_using_debug_link(&mut self) -> bool {
@@ -584,13 +750,37 @@ impl<'a, 'b> ElfParser<'a, 'b> {
     }
 
     unsafe fn load_symbol_table(&mut self, symtab: *const ElfSection) {
+        if (*symtab).sh_link >= (*self.header).e_shnum as _ || ((*symtab).sh_entsize as usize) < mem::size_of::<ElfSymbol>() {
+            return;
+        }
         let strtab = self.section((*symtab).sh_link as _);
-        let strings = self.at_section(strtab) as *const u8;
-        let mut symbols = self.at_section(symtab) ;
-        let symbols_end = symbols.offset((*symtab).sh_size as _);
-        while symbols < symbols_end {
+        let (mut strings_buf, mut symbols_buf) = (Vec::new(), Vec::new());
+        let strings = match self.section_data(strtab, &mut strings_buf) {
+            Some(strings) => strings,
+            None => return,
+        };
+        let mut symbols = match self.section_data(symtab, &mut symbols_buf) {
+            Some(symbols) => symbols as *const i8,
+            None => return,
+        };
+        let symbols_size = if symbols_buf.is_empty() {
+            (*symtab).sh_size as usize
+        } else {
+            symbols_buf.len()
+        };
+        let strings_size = if strings_buf.is_empty() {
+            (*strtab).sh_size as usize
+        } else {
+            strings_buf.len()
+        };
+        // the names are read as the C strings, the table must end with the NUL.
+        if strings_size == 0 || *strings.add(strings_size - 1) != 0 {
+            return;
+        }
+        let symbols_end = symbols.add(symbols_size);
+        while symbols.add(mem::size_of::<ElfSymbol>()) <= symbols_end {
             let sym =  symbols as *const ElfSymbol;
-            if (*sym).st_name != 0 && (*sym).st_value != 0 {
+            if (*sym).st_name != 0 && (*sym).st_value != 0 && ((*sym).st_name as usize) < strings_size {
                 // Skip special AArch64 mapping symbols: $x and $d
                 if (*sym).st_size != 0 || (*sym).st_info != 0 || *strings.add((*sym).st_name as _) != b'$' {
                     let sec_base = self.base.add((*sym).st_value as _);
@@ -622,16 +812,26 @@ impl<'a, 'b> ElfParser<'a, 'b> {
         }
     }
 
+    /// build the frame table from the .eh_frame_hdr of the loaded image.
     unsafe fn parse_dwarf_info(&mut self) {
-        
+        if !DWARF_SUPPORTED {
+            return;
+        }
+        let eh_frame_hdr = self.find_program_header(libc::PT_GNU_EH_FRAME);
+        if !eh_frame_hdr.is_null() {
+            let dwarf = DwarfParser::new(self.cc.name_str(), self.base, self.at_programhdr(eh_frame_hdr));
+            self.cc.set_dwarf_table(dwarf.into_table());
+        }
     }
 
     unsafe fn parse_program_headers(cc: &'a mut CodeCache, base: *const i8, end: *const i8) {
         let mut elf_parser = ElfParser::new(cc, base, base, None);
         if elf_parser.valid_header() && base.offset((*elf_parser.header).e_phoff as _) < end {
             elf_parser.set_text_base(base);
+            elf_parser.cc.set_exec_image((*elf_parser.header).e_type == ET_EXEC);
             elf_parser.calc_virtual_local_address();
             elf_parser.parse_dynamic_section();
+            elf_parser.parse_dwarf_info();
         }
     }
 
@@ -736,9 +936,32 @@ impl<'a, 'b> ElfParser<'a, 'b> {
     }
 }
 
+/// decompress the SHF_COMPRESSED section, the data starts with the compression header.
+fn decompress_section(data: &[u8], out: &mut Vec<u8>) -> Option<()> {
+    let header_size = mem::size_of::<ElfChdr>();
+    if data.len() < header_size {
+        return None;
+    }
+    let header = unsafe { ptr::read_unaligned(data.as_ptr() as *const ElfChdr) };
+    let size = header.ch_size as usize;
+    let compressed = &data[header_size..];
+    out.clear();
+    out.reserve(size);
+    let result = match header.ch_type {
+        ELFCOMPRESS_ZLIB => flate2::read::ZlibDecoder::new(compressed).read_to_end(out).ok(),
+        ELFCOMPRESS_ZSTD => ruzstd::StreamingDecoder::new(compressed)
+            .ok()?
+            .read_to_end(out)
+            .ok(),
+        _ => None,
+    };
+    result.filter(|_| out.len() == size).map(|_| ())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::profiler::MAX_CODE_CACHE_ARRAY;
     #[test]
     fn test_memory_desc() {
         let line = b"0060c000-0060d000 rw-p 0000c000 fd:00 100694562                          /usr/bin/cat\0";
@@ -764,4 +987,278 @@ mod test {
         let desc = MemoryMapDesc::parse(line);
         assert_eq!(desc.is_empty_file(), true);
     }
+
+    #[test]
+    fn test_decompress_section() {
+        use std::io::Write;
+        let data: Vec<u8> = (0..1024u32).map(|i| (i % 7) as u8).collect();
+        let header = |typ: u32| {
+            let chdr = ElfChdr {
+                ch_type: typ,
+                #[cfg(target_pointer_width = "64")]
+                ch_reserved: 0,
+                ch_size: data.len() as _,
+                ch_addralign: 1,
+            };
+            let ptr = &chdr as *const ElfChdr as *const u8;
+            unsafe { slice::from_raw_parts(ptr, mem::size_of::<ElfChdr>()) }.to_vec()
+        };
+        let mut out = Vec::new();
+
+        let mut zlib = header(ELFCOMPRESS_ZLIB);
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        zlib.extend_from_slice(&encoder.finish().unwrap());
+        assert!(decompress_section(&zlib, &mut out).is_some());
+        assert_eq!(out, data);
+
+        // the zstd frame with a single raw block.
+        let mut zstd = header(ELFCOMPRESS_ZSTD);
+        zstd.extend_from_slice(&0xFD2FB528u32.to_le_bytes());
+        zstd.push(0x60);
+        zstd.extend_from_slice(&(data.len() as u16 - 256).to_le_bytes());
+        let block = (data.len() as u32) << 3 | 1;
+        zstd.extend_from_slice(&block.to_le_bytes()[..3]);
+        zstd.extend_from_slice(&data);
+        assert!(decompress_section(&zstd, &mut out).is_some());
+        assert_eq!(out, data);
+
+        assert!(decompress_section(&header(3), &mut out).is_none());
+        assert!(decompress_section(&zlib[..zlib.len() - 8], &mut out).is_none());
+    }
+
+    fn as_bytes<T>(value: &T) -> &[u8] {
+        unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+    }
+
+    /// the relocatable elf of the sections (name, type, link, entsize, data), the .shstrtab is added
+    /// last. the image is kept in the u64s for the alignment of the headers.
+    fn elf_image(sections: &[(&str, u32, u32, usize, Vec<u8>)]) -> Vec<u64> {
+        let mut names = vec![0u8];
+        let mut name_offs = Vec::new();
+        for name in sections.iter().map(|s| s.0).chain([".shstrtab"]) {
+            name_offs.push(names.len());
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+        let mut image = vec![0u8; mem::size_of::<ElfHeader>()];
+        let mut headers = vec![unsafe { mem::zeroed::<ElfSection>() }];
+        let all = sections.iter().map(|(_, typ, link, entsize, data)| (*typ, *link, *entsize, data));
+        for (i, (typ, link, entsize, data)) in all.chain([(3, 0, 0, &names)]).enumerate() {
+            let mut header: ElfSection = unsafe { mem::zeroed() };
+            header.sh_name = name_offs[i] as _;
+            header.sh_type = typ;
+            header.sh_link = link;
+            header.sh_entsize = entsize as _;
+            header.sh_offset = image.len() as _;
+            header.sh_size = data.len() as _;
+            image.extend_from_slice(data);
+            headers.push(header);
+        }
+        image.resize(image.len().next_multiple_of(8), 0);
+        let mut header: ElfHeader = unsafe { mem::zeroed() };
+        header.e_ident[..7].copy_from_slice(&[0x7f, b'E', b'L', b'F', ELFCLASS_SUPPORTED, libc::ELFDATA2LSB, 1]);
+        header.e_shoff = image.len() as _;
+        header.e_shentsize = mem::size_of::<ElfSection>() as _;
+        header.e_shnum = headers.len() as _;
+        header.e_shstrndx = (headers.len() - 1) as _;
+        image[..mem::size_of::<ElfHeader>()].copy_from_slice(as_bytes(&header));
+        for header in headers.iter() {
+            image.extend_from_slice(as_bytes(header));
+        }
+        image.resize(image.len().next_multiple_of(8), 0);
+        image.chunks(8).map(|b| u64::from_ne_bytes(b.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_load_symbols_using_debugdata() {
+        let mut symbol: ElfSymbol = unsafe { mem::zeroed() };
+        symbol.st_name = 1;
+        symbol.st_value = 0x40;
+        symbol.st_size = 0x10;
+        symbol.st_info = 2;
+        let mut symtab = vec![0u8; mem::size_of::<ElfSymbol>()];
+        symtab.extend_from_slice(as_bytes(&symbol));
+        let entsize = mem::size_of::<ElfSymbol>();
+        let debug_elf = |symtab_size: usize| {
+            let inner = elf_image(&[
+                (".symtab", SHT_SYMTAB, 2, entsize, symtab[..symtab_size].to_vec()),
+                (".strtab", 3, 0, 0, b"\0mini_func\0".to_vec()),
+            ]);
+            let inner: Vec<u8> = inner.iter().flat_map(|w| w.to_ne_bytes()).collect();
+            let mut xz = Vec::new();
+            lzma_rs::xz_compress(&mut &inner[..], &mut xz).unwrap();
+            (inner, elf_image(&[(".gnu_debugdata", SHT_PROGBITS, 0, 0, xz)]))
+        };
+        let load = |image: &[u64]| {
+            let mut cc = unsafe { CodeCache::new(c_str!("test"), 0) };
+            let loaded = unsafe {
+                ElfParser::new(&mut cc, 0x1000 as _, image.as_ptr() as _, None).load_symbols_using_debugdata()
+            };
+            let blobs: Vec<(usize, String)> =
+                cc.code_blobs().iter().map(|b| (b.start() as usize, b.name_str().to_string())).collect();
+            (loaded, blobs)
+        };
+
+        let (_, image) = debug_elf(symtab.len());
+        assert_eq!(load(&image), (true, vec![(0x1040, "mini_func".to_string())]));
+
+        // the symtab past the end of the decompressed elf.
+        let (inner, _) = debug_elf(symtab.len());
+        let header = unsafe { &*(inner.as_ptr() as *const ElfHeader) };
+        let symtab_header = header.e_shoff as usize + header.e_shentsize as usize;
+        let mut broken = inner.clone();
+        let mut section: ElfSection = unsafe { ptr::read_unaligned(broken[symtab_header..].as_ptr() as _) };
+        section.sh_size = (inner.len() * 2) as _;
+        broken[symtab_header..symtab_header + mem::size_of::<ElfSection>()].copy_from_slice(as_bytes(&section));
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut &broken[..], &mut xz).unwrap();
+        let image = elf_image(&[(".gnu_debugdata", SHT_PROGBITS, 0, 0, xz)]);
+        assert_eq!(load(&image), (false, Vec::new()));
+
+        // the truncated symbol is skipped.
+        let (_, image) = debug_elf(symtab.len() - 1);
+        assert_eq!(load(&image), (true, Vec::new()));
+    }
+
+    #[test]
+    fn test_parse_dwarf_info() {
+        let code_caches = CodeCacheArray::new(MAX_CODE_CACHE_ARRAY as _);
+        SymbolParserImpl::new().parse_libraries(&code_caches);
+        let pc = test_parse_dwarf_info as *const i8;
+        let cc = code_caches.iter().find(|cc| cc.contains(pc)).expect("the test binary is parsed");
+        if DWARF_SUPPORTED {
+            assert!(!cc.dwarf_table().is_empty());
+            assert!(cc.dwarf_table().windows(2).all(|w| w[0].loc <= w[1].loc));
+            // the cfa is above the return address at the function entry.
+            let f = cc.find_frame_desc(pc);
+            assert_eq!(f.cfa_reg(), crate::dwarf::DW_REG_SP);
+            assert_eq!(f.cfa_off() as usize, std::mem::size_of::<usize>());
+        }
+    }
+
+    #[test]
+    fn test_parse_dlopen_library() {
+        let has_libz = |code_caches: &CodeCacheArray| {
+            code_caches.iter().any(|cc| cc.name_str().contains("libz.so"))
+        };
+        let code_caches = CodeCacheArray::new(MAX_CODE_CACHE_ARRAY as _);
+        let mut parser = SymbolParserImpl::new();
+        parser.parse_libraries(&code_caches);
+        let count = code_caches.count();
+        // the test binary, the libc and the vdso at least.
+        assert!(count > 2);
+        assert!(code_caches.iter().all(|cc| !cc.name_str().ends_with('\n')));
+        let loaded = has_libz(&code_caches);
+
+        let handle = unsafe { libc::dlopen(c_str!("libz.so.1") as _, libc::RTLD_NOW) };
+        // the libz isn't installed on the host.
+        if handle.is_null() {
+            return;
+        }
+        parser.parse_libraries(&code_caches);
+        assert!(has_libz(&code_caches));
+        if loaded {
+            return;
+        }
+        assert!(code_caches.count() > count);
+
+        // the dlclosed library is unmapped, its code cache is removed.
+        assert_eq!(unsafe { libc::dlclose(handle) }, 0);
+        parser.parse_libraries(&code_caches);
+        assert!(!has_libz(&code_caches));
+        assert_eq!(code_caches.iter().count(), count);
+    }
+
+    #[test]
+    fn test_parse_kernel_symbols() {
+        let code_caches = CodeCacheArray::new(MAX_CODE_CACHE_ARRAY as _);
+        // the kallsyms may be hidden by the kptr_restrict.
+        if !SymbolParserImpl::new().parse_kernel_symbols(&code_caches) {
+            return;
+        }
+        code_caches.update_index();
+        let kernel = code_caches.iter().next().unwrap();
+        assert_eq!(kernel.name_str(), "[kernel]");
+        let blob = &kernel.code_blobs()[kernel.code_blobs().len() / 2];
+        let found = code_caches.find(blob.start()).expect("the kernel range is indexed");
+        assert_eq!(found.name_str(), "[kernel]");
+        assert!(found.binary_search(blob.start()).is_some());
+    }
+
+    #[inline(never)]
+    fn locate_me() -> usize {
+        locate_me as usize
+    }
+
+    #[test]
+    fn test_parse_debug_line() {
+        let code_caches = CodeCacheArray::new(MAX_CODE_CACHE_ARRAY as _);
+        SymbolParserImpl::new().parse_libraries(&code_caches);
+        let pc = locate_me() as *const i8;
+        let cc = code_caches.iter().find(|cc| cc.contains(pc)).expect("the test binary is parsed");
+        assert!(cc.debug_file().is_some());
+
+        let mut resolver = crate::native_line::NativeLines::new();
+        let mut lines = Vec::new();
+        assert!(resolver.resolve(cc, pc, &mut lines));
+        let last = lines.last().unwrap();
+        assert!(last.file.as_deref().unwrap().ends_with("symbol_linux.rs"));
+        assert!(last.line.is_some());
+    }
+
+    #[test]
+    fn test_parse_with_concurrent_lookups() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let code_caches = Arc::new(CodeCacheArray::new(MAX_CODE_CACHE_ARRAY as _));
+        let done = Arc::new(AtomicBool::new(false));
+        let pc = test_parse_with_concurrent_lookups as *const i8 as usize;
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let code_caches = code_caches.clone();
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    let mut last_count = 0;
+                    while !done.load(Ordering::Acquire) {
+                        let count = code_caches.count();
+                        assert!(count >= last_count);
+                        last_count = count;
+                        if let Some(cc) = code_caches.find(pc as _) {
+                            assert!(cc.contains(pc as _));
+                            let _ = cc.binary_search(pc as _);
+                        }
+                        assert!(code_caches.iter().count() >= count);
+                    }
+                })
+            })
+            .collect();
+        let mut parser = SymbolParserImpl::new();
+        parser.parse_libraries(&code_caches);
+        code_caches.update_index();
+        // the libraries parsed are skipped by the later parse.
+        let count = code_caches.count();
+        assert!(count > 2);
+        assert!(code_caches.iter().any(|cc| cc.name_str().contains("libc.so")));
+        let names: HashSet<&str> = code_caches.iter().map(|cc| cc.name_str()).collect();
+        assert_eq!(names.len(), count);
+        parser.parse_libraries(&code_caches);
+        assert_eq!(code_caches.count(), count);
+        for i in 0..64 {
+            let mut cc = unsafe { CodeCache::new(c_str!("[test]") as _, (count + i) as _) };
+            unsafe { cc.add((0x1000 * (i + 1)) as _, 0x100, c_str!("f") as _, true) };
+            cc.sort();
+            code_caches.add(cc);
+            if i & 15 == 0 {
+                code_caches.update_index();
+            }
+        }
+        done.store(true, Ordering::Release);
+        for r in readers {
+            r.join().unwrap();
+        }
+        assert!(code_caches.find(pc as _).is_some());
+        assert_eq!(code_caches.iter().count(), count + 64);
+    }
 }
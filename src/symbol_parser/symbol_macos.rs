@@ -1,7 +1,7 @@
 #![allow(deprecated)]
 use std::{collections::HashSet, ffi::CStr, mem};
 
-use crate::{code_cache::CodeCache, log_info, profiler::MAX_CODE_CACHE_ARRAY};
+use crate::{code_cache::{CodeCache, CodeCacheArray}, log_info};
 
 #[repr(C)]
 #[allow(non_camel_case_types)]
@@ -196,7 +196,12 @@ impl SymbolParserImpl {
         }
     }
 
-    pub fn parse_libraries(&mut self, code_caches: &mut Vec<CodeCache>, _parse_kernel: bool) {
+    /// the kernel symbols aren't readable on the macos.
+    pub fn parse_kernel_symbols(&mut self, _code_caches: &CodeCacheArray) -> bool {
+        false
+    }
+
+    pub fn parse_libraries(&mut self, code_caches: &CodeCacheArray) {
         unsafe {
             let count = libc::_dyld_image_count();
             for i in 0..count {
@@ -213,10 +218,10 @@ impl SymbolParserImpl {
                     continue;
                 }
                 let _hanlde = DlHandle { handle };
-                let array_len = code_caches.len();
-                if array_len >= MAX_CODE_CACHE_ARRAY as _ {
+                if code_caches.is_full() {
                     break;
                 }
+                let array_len = code_caches.count();
                 let mut cc = CodeCache::new(dll_name, array_len as _);
 
                 let mut parser = MachObjectParser::new(&mut cc, image_base);
@@ -227,7 +232,7 @@ impl SymbolParserImpl {
                     );
                 }
                 cc.sort();
-                code_caches.push(cc);
+                code_caches.add(cc);
             }
         }
     }
@@ -0,0 +1,151 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+/// keep the recent lock waits, the older are only counted.
+const MAX_LOCK_WAITS: usize = 65536;
+
+/// the lifetime of the thread, the start is None if the thread is started before the agent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadSpan {
+    pub tid: u32,
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+/// the wait of the contended monitor, from the MonitorContendedEnter to the MonitorContendedEntered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockWait {
+    pub tid: u32,
+    pub start: u64,
+    pub duration: u64,
+    /// the class name of the monitor object.
+    pub class: String,
+}
+
+struct Timeline {
+    threads: HashMap<u32, ThreadSpan>,
+    /// the monitor waiting by the tid, (start, class).
+    waiting: HashMap<u32, (u64, String)>,
+    waits: VecDeque<LockWait>,
+    total_waits: u64,
+}
+
+/// Record the thread start/end and the lock waits by the jvmti events, the times are
+/// the monotonic nano time. The events are posted by the thread itself, so the tid is
+/// the current os thread.
+pub struct ThreadTimeline {
+    inner: Mutex<Timeline>,
+}
+
+impl ThreadTimeline {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Timeline {
+                threads: HashMap::new(),
+                waiting: HashMap::new(),
+                waits: VecDeque::new(),
+                total_waits: 0,
+            }),
+        }
+    }
+
+    /// clear the ended threads and the waits, the live threads are kept.
+    pub fn reset(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.threads.retain(|_, span| span.end.is_none());
+            inner.waits.clear();
+            inner.total_waits = 0;
+        }
+    }
+
+    pub fn thread_start(&self, tid: u32, time: u64) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.threads.insert(tid, ThreadSpan { tid, start: Some(time), end: None });
+        }
+    }
+
+    pub fn thread_end(&self, tid: u32, time: u64) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner
+                .threads
+                .entry(tid)
+                .or_insert(ThreadSpan { tid, start: None, end: None })
+                .end = Some(time);
+            inner.waiting.remove(&tid);
+        }
+    }
+
+    pub fn lock_enter(&self, tid: u32, time: u64, class: String) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.waiting.insert(tid, (time, class));
+        }
+    }
+
+    pub fn lock_entered(&self, tid: u32, time: u64) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if let Some((start, class)) = inner.waiting.remove(&tid) {
+                if inner.waits.len() >= MAX_LOCK_WAITS {
+                    inner.waits.pop_front();
+                }
+                let duration = time.saturating_sub(start);
+                inner.waits.push_back(LockWait { tid, start, duration, class });
+                inner.total_waits += 1;
+            }
+        }
+    }
+
+    /// the known threads, the tid order.
+    pub fn threads(&self) -> Vec<ThreadSpan> {
+        let mut threads: Vec<ThreadSpan> = match self.inner.lock() {
+            Ok(inner) => inner.threads.values().copied().collect(),
+            Err(_) => return Vec::new(),
+        };
+        threads.sort_by_key(|span| span.tid);
+        threads
+    }
+
+    /// the recent lock waits, the oldest first.
+    pub fn lock_waits(&self) -> Vec<LockWait> {
+        match self.inner.lock() {
+            Ok(inner) => inner.waits.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn total_lock_waits(&self) -> u64 {
+        self.inner.lock().map_or(0, |inner| inner.total_waits)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timeline() {
+        let timeline = ThreadTimeline::new();
+        timeline.thread_start(2, 10);
+        timeline.lock_enter(2, 20, "java.lang.Object".into());
+        timeline.lock_entered(2, 35);
+        // the entered without the enter is ignored.
+        timeline.lock_entered(3, 40);
+        timeline.thread_end(3, 50);
+        assert_eq!(
+            timeline.threads(),
+            vec![
+                ThreadSpan { tid: 2, start: Some(10), end: None },
+                ThreadSpan { tid: 3, start: None, end: Some(50) },
+            ]
+        );
+        let waits = timeline.lock_waits();
+        assert_eq!(waits.len(), 1);
+        assert_eq!((waits[0].start, waits[0].duration), (20, 15));
+        assert_eq!(waits[0].class, "java.lang.Object");
+
+        timeline.reset();
+        assert_eq!(timeline.threads().len(), 1);
+        assert_eq!(timeline.total_lock_waits(), 0);
+    }
+}
@@ -3,11 +3,14 @@ use crate::ctrl_svr::CtrlSvr;
 use crate::jvmti::{JNIEnv, JNIEnvPtr, JavaVM, JvmtiEnv, JvmtiEnvPtr, JvmtiEventCallbacks,};
 use crate::jvmti_native::{
     jfieldID, jint, jmethodID, jthread, jvmtiAddrLocationMap, JVMTI_ENABLE,
-    JVMTI_EVENT_COMPILED_METHOD_LOAD, JVMTI_EVENT_DYNAMIC_CODE_GENERATED, JVMTI_EVENT_THREAD_END,
+    JVMTI_EVENT_COMPILED_METHOD_LOAD, JVMTI_EVENT_COMPILED_METHOD_UNLOAD, JVMTI_EVENT_DYNAMIC_CODE_GENERATED, JVMTI_EVENT_THREAD_END,
     JVMTI_EVENT_THREAD_START, JVMTI_EVENT_VM_INIT, JVMTI_EVENT_CLASS_LOAD, jclass, jvmtiCapabilities, JVMTI_EVENT_CLASS_PREPARE,
+    JVMTI_EVENT_GARBAGE_COLLECTION_START, JVMTI_EVENT_GARBAGE_COLLECTION_FINISH,
+    JVMTI_EVENT_MONITOR_CONTENDED_ENTER, JVMTI_EVENT_MONITOR_CONTENDED_ENTERED, jobject,
 };
+use crate::os::OS;
 use crate::profiler::Profiler;
-use crate::vm_struct::{CodeHeap, VMStruct};
+use crate::vm_struct::{CodeHeap, JavaWalker, VMStruct};
 use crate::{c_str, check_null, get_vm_mut, jni_method, log_error, get_vm, cstr_2_str};
 use std::mem::{self, MaybeUninit};
 use std::ptr;
@@ -25,8 +28,39 @@ pub const MAX_FRAMES: usize = 2048;
 pub const ASGCTFAIL_TICKS_NO_CLASS_LOAD: i32 = -1;
 pub const ASGCTFAIL_TICKS_GCACTIVE: i32 = -2;
 pub const ASGCTFAIL_TICKS_UNKNOWN_NOT_JAVA: i32 = -3;
-pub const ASGCTFAIL_TICKS_NOT_WALKABLE_JAVA: i32 = -4;
+pub const ASGCTFAIL_TICKS_NOT_WALKABLE_NOT_JAVA: i32 = -4;
 pub const ASGCTFAIL_TICKS_UNKNOWN_JAVA: i32 = -5;
+pub const ASGCTFAIL_TICKS_NOT_WALKABLE_JAVA: i32 = -6;
+pub const ASGCTFAIL_TICKS_UNKNOWN_STATE: i32 = -7;
+pub const ASGCTFAIL_TICKS_THREAD_EXIT: i32 = -8;
+pub const ASGCTFAIL_TICKS_DEOPT: i32 = -9;
+pub const ASGCTFAIL_TICKS_SAFEPOINT: i32 = -10;
+pub const ASGCTFAIL_TICKS_SKIPPED: i32 = -11;
+pub const ASGCTFAIL_TYPES: usize = 12;
+
+/// the error frame names, index by the negative ASGCTFAIL_TICKS code.
+pub const ASGCTFAIL_NAMES: [&str; ASGCTFAIL_TYPES] = [
+    "[no_java_frame]",
+    "[no_class_load]",
+    "[gc_active]",
+    "[unknown_not_java]",
+    "[not_walkable_not_java]",
+    "[unknown_java]",
+    "[not_walkable_java]",
+    "[unknown_state]",
+    "[thread_exit]",
+    "[deopt]",
+    "[safepoint]",
+    "[skipped]",
+];
+
+#[inline(always)]
+pub fn asgct_failure_name(code: i32) -> &'static str {
+    match ASGCTFAIL_NAMES.get(code.unsigned_abs() as usize) {
+        Some(name) => name,
+        None => "[unknown_error]",
+    }
+}
 
 
 pub const BCI_NATIVE_FRAME: i32 = -10;
@@ -38,14 +72,27 @@ pub const BCI_PARK: i32 = -15;
 pub const BCI_THREADID: i32 = -16;
 pub const BCI_ERROR: i32 = -17;
 pub const BCI_INSTRUMENT: i32 = -18;
+pub const BCI_GC: i32 = -19;
+/// the stub or the interpreter in the code heap, the method_id is the blob name.
+pub const BCI_CODE_BLOB: i32 = -20;
+/// the native frame recorded with the line detail, the method_id is the pc.
+pub const BCI_NATIVE_PC: i32 = -21;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct JVMPICallFrame {
     pub bci: jint,
     pub method_id: jmethodID,
 }
 
+impl JVMPICallFrame {
+    /// the special frames have the bci below the BCI_NATIVE_FRAME.
+    #[inline(always)]
+    pub fn is_java(&self) -> bool {
+        !self.method_id.is_null() && self.bci > BCI_NATIVE_FRAME
+    }
+}
+
 impl Default for JVMPICallFrame {
     fn default() -> Self {
         Self {
@@ -159,6 +206,11 @@ impl VM {
         jvmti_callback.ThreadEnd = Some(Self::jvm_thread_end);
         jvmti_callback.DynamicCodeGenerated = Some(Self::jvm_dynamic_code_generated);
         jvmti_callback.CompiledMethodLoad = Some(Self::jvm_compiled_method_load);
+        jvmti_callback.CompiledMethodUnload = Some(Self::jvm_compiled_method_unload);
+        jvmti_callback.GarbageCollectionStart = Some(Self::jvm_gc_start);
+        jvmti_callback.GarbageCollectionFinish = Some(Self::jvm_gc_finish);
+        jvmti_callback.MonitorContendedEnter = Some(Self::jvm_monitor_contended_enter);
+        jvmti_callback.MonitorContendedEntered = Some(Self::jvm_monitor_contended_entered);
         self.jvmti
             .set_event_callbacks(
                 &jvmti_callback,
@@ -182,6 +234,11 @@ impl VM {
         //JVMTI_EVENT_CLASS_LOAD must be enable, if this value is disable, the AsyncGetCallTrace will return -1
         jvmti_enable!(JVMTI_EVENT_CLASS_LOAD);
         jvmti_enable!(JVMTI_EVENT_COMPILED_METHOD_LOAD);
+        jvmti_enable!(JVMTI_EVENT_COMPILED_METHOD_UNLOAD);
+        jvmti_enable!(JVMTI_EVENT_GARBAGE_COLLECTION_START);
+        jvmti_enable!(JVMTI_EVENT_GARBAGE_COLLECTION_FINISH);
+        jvmti_enable!(JVMTI_EVENT_MONITOR_CONTENDED_ENTER);
+        jvmti_enable!(JVMTI_EVENT_MONITOR_CONTENDED_ENTERED);
 
         self.jvmti.generate_events(JVMTI_EVENT_DYNAMIC_CODE_GENERATED);
         self.jvmti.generate_events(JVMTI_EVENT_COMPILED_METHOD_LOAD);
@@ -207,6 +264,8 @@ impl VM {
         let mut method_count = 0;
         let mut methods: *mut jmethodID = ptr::null_mut();
         if let Some(0) = jvmti.get_class_methods(class, &mut method_count, &mut methods) {
+            let methods_slice = unsafe { std::slice::from_raw_parts(methods, method_count.max(0) as _) };
+            vm.profiler().intern_methods(jvmti, methods_slice);
             jvmti.deallocate(methods as _);
         }
     }
@@ -231,16 +290,35 @@ impl VM {
         get_vm().load_method_ids(&jvmti, &jni, class);
     }
 
+    /// the thread events are posted by the thread itself.
     unsafe extern "C" fn jvm_thread_start(jvmti: JvmtiEnvPtr, jni: JNIEnvPtr, thread: jthread) {
-        get_vm_mut()
-            .profiler_mut()
-            .update_thread_info(jvmti.into(), jni.into(), thread);
+        let profiler = get_vm_mut().profiler_mut();
+        profiler.update_thread_info(jvmti.into(), jni.into(), thread);
+        profiler.thread_timeline().thread_start(OS::thread_id(), OS::nano_time());
     }
 
     unsafe extern "C" fn jvm_thread_end(jvmti: JvmtiEnvPtr, jni: JNIEnvPtr, thread: jthread) {
-        get_vm_mut()
-            .profiler_mut()
-            .update_thread_info(jvmti.into(), jni.into(), thread);
+        let profiler = get_vm_mut().profiler_mut();
+        profiler.update_thread_info(jvmti.into(), jni.into(), thread);
+        profiler.thread_timeline().thread_end(OS::thread_id(), OS::nano_time());
+    }
+
+    unsafe extern "C" fn jvm_monitor_contended_enter(
+        jvmti: JvmtiEnvPtr,
+        jni: JNIEnvPtr,
+        _thread: jthread,
+        object: jobject,
+    ) {
+        get_vm().profiler.monitor_contended_enter(&jvmti.into(), &jni.into(), object);
+    }
+
+    unsafe extern "C" fn jvm_monitor_contended_entered(
+        _jvmti: JvmtiEnvPtr,
+        _jni: JNIEnvPtr,
+        _thread: jthread,
+        _object: jobject,
+    ) {
+        get_vm().profiler.thread_timeline().lock_entered(OS::thread_id(), OS::nano_time());
     }
 
     unsafe extern "C" fn jvm_dynamic_code_generated(
@@ -256,16 +334,38 @@ impl VM {
 
     unsafe extern "C" fn jvm_compiled_method_load(
         _jvmti: JvmtiEnvPtr,
-        _method: jmethodID,
+        method: jmethodID,
         code_size: jint,
         code_addr: *const libc::c_void,
-        _map_length: jint,
-        _map: *const jvmtiAddrLocationMap,
-        _compile_info: *const libc::c_void,
+        map_length: jint,
+        map: *const jvmtiAddrLocationMap,
+        compile_info: *const libc::c_void,
     ) {
+        let map = if map.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts(map, map_length.max(0) as _)
+        };
         get_vm_mut()
             .profiler
-            .add_java_method(code_addr as _, code_size as _);
+            .add_java_method(method, code_addr as _, code_size as _, map, compile_info);
+    }
+
+    unsafe extern "C" fn jvm_compiled_method_unload(
+        _jvmti: JvmtiEnvPtr,
+        method: jmethodID,
+        code_addr: *const libc::c_void,
+    ) {
+        get_vm_mut().profiler.remove_java_method(method, code_addr as _);
+    }
+
+    /// called in the GC pause, only the raw monitor functions of jvmti can be used here.
+    unsafe extern "C" fn jvm_gc_start(_jvmti: JvmtiEnvPtr) {
+        get_vm().profiler.gc_timeline().gc_start();
+    }
+
+    unsafe extern "C" fn jvm_gc_finish(_jvmti: JvmtiEnvPtr) {
+        get_vm().profiler.gc_timeline().gc_finish();
     }
 
     extern "C" fn jvm_init(jvmti: JvmtiEnvPtr, jni: JNIEnvPtr, _jthr: jthread) {
@@ -426,6 +526,11 @@ impl VM {
         self.vm_struct.code_heap()
     }
 
+    #[inline(always)]
+    pub fn java_walker(&self) -> JavaWalker<'_> {
+        self.vm_struct.java_walker()
+    }
+
     #[inline(always)]
     pub unsafe fn update_heap_bounds(&mut self, start: *const i8, end: *const i8) {
         self.vm_struct.update_bounds(start, end);
@@ -1,7 +1,11 @@
 mod code_heap;
+mod java_walker;
 mod nmethod;
+mod vmmethod;
 mod vmthread;
 pub use code_heap::CodeHeap;
+pub use java_walker::JavaWalker;
+pub use nmethod::NMethod;
 use libc::uintptr_t;
 use std::{
     ffi::CStr,
@@ -48,6 +52,8 @@ pub struct VMStruct {
     osthread_id_offset: i32,
     anchor_sp_offset: i32,
     anchor_pc_offset: i32,
+    /// only declared by the platforms keeping the fp in the anchor, like x86 and aarch64.
+    anchor_fp_offset: i32,
     frame_size_offset: i32,
     frame_complete_offset: i32,
     code_heap_addr: *const *const i8,
@@ -140,6 +146,7 @@ impl Display for VMStruct {
         let _ = write!(f, ", osthread_id_offset:{:#X}", self.osthread_id_offset);
         let _ = write!(f, ", anchor_sp_offset:{:#X}", self.anchor_sp_offset);
         let _ = write!(f, ", anchor_pc_offset:{:#X}", self.anchor_pc_offset);
+        let _ = write!(f, ", anchor_fp_offset:{:#X}", self.anchor_fp_offset);
         let _ = write!(f, ", frame_size_offset:{:#X}", self.frame_size_offset);
         let _ = write!(
             f,
@@ -215,6 +222,7 @@ impl VMStruct {
             osthread_id_offset: -1,
             anchor_sp_offset: -1,
             anchor_pc_offset: -1,
+            anchor_fp_offset: -1,
             frame_size_offset: -1,
             frame_complete_offset: -1,
             code_heap_addr: ptr::null(),
@@ -273,6 +281,11 @@ impl VMStruct {
         CodeHeap::new(self)
     }
 
+    #[inline(always)]
+    pub fn java_walker(&self) -> JavaWalker<'_> {
+        JavaWalker::new(self)
+    }
+
     pub fn initial(&mut self, libjvm: Option<&'static CodeCache>) {
         self.libjvm = libjvm;
         unsafe {
@@ -412,6 +425,7 @@ impl VMStruct {
                 b"JavaFrameAnchor" => match filed_sl {
                     b"_last_Java_sp" => asign_offset!(self.anchor_sp_offset),
                     b"_last_Java_pc" => asign_offset!(self.anchor_pc_offset),
+                    b"_last_Java_fp" => asign_offset!(self.anchor_fp_offset),
                     _ => {}
                 },
                 b"CodeBlob" => match filed_sl {
@@ -25,7 +25,7 @@ impl<'a> CodeHeap<'a> {
             && pc < *(heap.add(mem_hight_off) as *const *const i8)
     }
 
-    unsafe fn find_nmethod_in_heap(&self, heap: *const i8, pc: *const i8) -> Option<NMethod> {
+    unsafe fn find_nmethod_in_heap(&self, heap: *const i8, pc: *const i8) -> Option<NMethod<'a>> {
         let start_off = (self.0.code_heap_memory_offset + self.0.vs_low_offset) as _;
         let heap_start = *(heap.add(start_off) as *const *const i8);
         let segmap_off = (self.0.code_heap_segmap_offset + self.0.vs_low_offset) as _;
@@ -42,13 +42,13 @@ impl<'a> CodeHeap<'a> {
         let block = heap_start.add((idx << self.0.code_heap_segment_shift) as usize);
         if (*block.add(mem::size_of::<isize>())) > 0 {
             let nmethod_addr = block.add(2 * mem::size_of::<isize>());
-            Some(NMethod::new(nmethod_addr, self.0.nmethod_name_offset))
+            Some(NMethod::new(nmethod_addr, self.0))
         } else {
             None
         }
     }
 
-    pub unsafe fn find_nmethod(&self, pc: *const i8) -> Option<NMethod> {
+    pub unsafe fn find_nmethod(&self, pc: *const i8) -> Option<NMethod<'a>> {
         if self.contain(self.0.code_heap[0], pc) {
             return self.find_nmethod_in_heap(self.0.code_heap[0], pc);
         }
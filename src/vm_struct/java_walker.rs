@@ -0,0 +1,146 @@
+use std::mem;
+
+use libc::uintptr_t;
+
+use crate::{
+    frame_type::FrameType,
+    jvmti_native::jmethodID,
+    stack_walker::StackContext,
+    vm::{JVMPICallFrame, BCI_CODE_BLOB},
+    get_vm,
+};
+
+use super::{vmmethod::{good_ptr, VMMethod}, VMStruct, VMThread};
+
+const SLOT: usize = mem::size_of::<uintptr_t>();
+const MAX_FRAME_SIZE: usize = 0x40000;
+/// the slots of the interpreter frame relative to the fp, same on x86_64 and aarch64.
+const INTERPRETER_FRAME_SENDER_SP_OFFSET: isize = -1;
+const INTERPRETER_FRAME_METHOD_OFFSET: isize = -3;
+const LINK_OFFSET: isize = 0;
+const RETURN_ADDR_OFFSET: isize = 1;
+
+/// Walk the java frames by the VMStructs, used when the AsyncGetCallTrace fails.
+/// The compiled frames are unwound by the frame size of the nmethod, the interpreted
+/// frames by the fp link. The bci of the compiled frame is from the pc descs of the jit code,
+/// otherwise the bci is unknown and reported as -1, so no line is printed.
+pub struct JavaWalker<'a>(&'a VMStruct);
+
+impl<'a> JavaWalker<'a> {
+    #[inline(always)]
+    pub fn new(vm_struct: &'a VMStruct) -> Self {
+        Self(vm_struct)
+    }
+
+    #[inline(always)]
+    pub fn available(&self) -> bool {
+        let vs = self.0;
+        vs.has_method_structs()
+            && vs.frame_size_offset >= 0
+            && vs.thread_anchor_offset >= 0
+            && vs.anchor_sp_offset >= 0
+            && vs.anchor_pc_offset >= 0
+    }
+
+    /// the last java frame recorded by the thread when it entered the native or VM code.
+    pub unsafe fn last_java_frame(&self, thread: &VMThread) -> Option<StackContext> {
+        let vs = self.0;
+        let anchor = thread.inner().offset(vs.thread_anchor_offset as _);
+        let sp = *(anchor.offset(vs.anchor_sp_offset as _) as *const uintptr_t);
+        if !good_ptr(sp as _) {
+            return None;
+        }
+        let mut pc = *(anchor.offset(vs.anchor_pc_offset as _) as *const *const ());
+        if pc.is_null() {
+            // the anchor is not marked walkable, the return address is below the sp.
+            pc = *(sp as *const *const ()).sub(1);
+        }
+        // the fp links the interpreted frame, it's only recorded when the anchor has it.
+        let fp = if vs.anchor_fp_offset >= 0 {
+            *(anchor.offset(vs.anchor_fp_offset as _) as *const uintptr_t)
+        } else {
+            0
+        };
+        let mut ctx = StackContext::new();
+        ctx.set(pc, sp, fp);
+        Some(ctx)
+    }
+
+    /// walk from the context into the frames, callee first, return the frame number.
+    pub unsafe fn walk(&self, ctx: &StackContext, frames: &mut [JVMPICallFrame]) -> usize {
+        let code_heap = self.0.code_heap();
+        let (mut pc, mut sp, mut fp) = (ctx.pc as *const i8, ctx.sp, ctx.fp);
+        let mut depth = 0;
+        while depth < frames.len() && code_heap.code_contains(pc) {
+            let nmethod = match code_heap.find_nmethod(pc) {
+                Some(nmethod) => nmethod,
+                None => break,
+            };
+            let prev_sp = sp;
+            if nmethod.is_interpreter() {
+                if fp < sp || fp >= sp + MAX_FRAME_SIZE || !good_ptr(fp as _) {
+                    break;
+                }
+                let link = fp as *const uintptr_t;
+                let method = *link.offset(INTERPRETER_FRAME_METHOD_OFFSET) as *const i8;
+                match self.method_id(method) {
+                    Some(id) => frames[depth] = Self::frame(FrameType::Interpreted, id),
+                    None => break,
+                }
+                depth += 1;
+                sp = *link.offset(INTERPRETER_FRAME_SENDER_SP_OFFSET);
+                pc = *link.offset(RETURN_ADDR_OFFSET) as _;
+                fp = *link.offset(LINK_OFFSET);
+            } else {
+                let frame_size = nmethod.frame_size();
+                if frame_size <= 0 {
+                    break;
+                }
+                if nmethod.is_nmethod() {
+                    // the pc descs of the CompiledMethodLoad give the inlined frames and the bci.
+                    let n = get_vm().profiler().find_jit_frames(pc, &mut frames[depth..]);
+                    if n > 0 {
+                        depth += n - 1;
+                    } else {
+                        match nmethod.method().and_then(|m| m.id()) {
+                            Some(id) => frames[depth] = Self::frame(FrameType::Compiled(nmethod.level()), id),
+                            None => break,
+                        }
+                    }
+                } else {
+                    // the runtime stub is named by the blob name.
+                    let name = nmethod.name();
+                    if name.is_null() {
+                        break;
+                    }
+                    frames[depth] = JVMPICallFrame {
+                        bci: BCI_CODE_BLOB,
+                        method_id: name as _,
+                    };
+                }
+                depth += 1;
+                // the sender is above the frame.
+                sp += frame_size as usize * SLOT;
+                pc = *(sp as *const *const i8).sub(1);
+                fp = *(sp as *const uintptr_t).sub(2);
+            }
+            if sp <= prev_sp || sp >= prev_sp + MAX_FRAME_SIZE || sp & (SLOT - 1) != 0 {
+                break;
+            }
+        }
+        depth
+    }
+
+    #[inline(always)]
+    unsafe fn method_id(&self, method: *const i8) -> Option<jmethodID> {
+        VMMethod::new(method, self.0)?.id().filter(|id| !id.is_null())
+    }
+
+    #[inline(always)]
+    fn frame(frame_type: FrameType, method_id: jmethodID) -> JVMPICallFrame {
+        JVMPICallFrame {
+            bci: frame_type.encode(-1),
+            method_id,
+        }
+    }
+}
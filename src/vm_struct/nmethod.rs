@@ -1,15 +1,17 @@
 use std::ffi::CStr;
 
-pub struct NMethod {
+use super::{vmmethod::VMMethod, VMStruct};
+
+pub struct NMethod<'a> {
     inner: *const i8,
-    nmethod_name_offset: i32,
+    vm_struct: &'a VMStruct,
 }
 
-impl NMethod {
-    pub fn new(inner: *const i8, nmethod_name_offset: i32) -> Self {
+impl<'a> NMethod<'a> {
+    pub fn new(inner: *const i8, vm_struct: &'a VMStruct) -> Self {
         Self {
             inner,
-            nmethod_name_offset,
+            vm_struct,
         }
     }
 
@@ -19,11 +21,49 @@ impl NMethod {
     }
 
     pub unsafe fn name(&self) -> *const i8 {
-        *(self.at(self.nmethod_name_offset as _) as *const *const i8)
+        *(self.at(self.vm_struct.nmethod_name_offset as _) as *const *const i8)
     }
 
     pub unsafe fn name_str(&self) -> &str {
         let name = CStr::from_ptr(self.name());
         std::str::from_utf8_unchecked(name.to_bytes())
     }
+
+    /// the blob is compiled java method.
+    pub unsafe fn is_nmethod(&self) -> bool {
+        let name = self.name();
+        if name.is_null() {
+            return false;
+        }
+        matches!(CStr::from_ptr(name).to_bytes(), b"nmethod" | b"native nmethod")
+    }
+
+    pub unsafe fn is_interpreter(&self) -> bool {
+        let name = self.name();
+        !name.is_null() && CStr::from_ptr(name).to_bytes() == b"Interpreter"
+    }
+
+    /// the compile level, 1-3 is C1, 4 is C2.
+    pub unsafe fn level(&self) -> i32 {
+        if self.vm_struct.nmethod_level_offset < 0 {
+            return 0;
+        }
+        *self.at(self.vm_struct.nmethod_level_offset as _) as i32
+    }
+
+    /// the frame size in words, include the return address.
+    pub unsafe fn frame_size(&self) -> i32 {
+        if self.vm_struct.frame_size_offset < 0 {
+            return 0;
+        }
+        *(self.at(self.vm_struct.frame_size_offset as _) as *const i32)
+    }
+
+    pub unsafe fn method(&self) -> Option<VMMethod<'a>> {
+        if self.vm_struct.nmethod_method_offset < 0 {
+            return None;
+        }
+        let method = *(self.at(self.vm_struct.nmethod_method_offset as _) as *const *const i8);
+        VMMethod::new(method, self.vm_struct)
+    }
 }
@@ -0,0 +1,61 @@
+use std::mem;
+
+use crate::jvmti_native::jmethodID;
+
+use super::VMStruct;
+
+const MIN_VALID_PTR: usize = 0x1000;
+
+/// the hotspot Method*.
+pub struct VMMethod<'a> {
+    inner: *const i8,
+    vm_struct: &'a VMStruct,
+}
+
+/// the nmethod found by the pc may be bogus, check the pointer before read.
+#[inline(always)]
+pub(crate) fn good_ptr(p: *const i8) -> bool {
+    p as usize >= MIN_VALID_PTR && (p as usize) & (mem::size_of::<usize>() - 1) == 0
+}
+
+impl<'a> VMMethod<'a> {
+    pub fn new(inner: *const i8, vm_struct: &'a VMStruct) -> Option<Self> {
+        if good_ptr(inner) {
+            Some(Self { inner, vm_struct })
+        } else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn at(&self, pos: i32) -> *const i8 {
+        self.inner.offset(pos as _)
+    }
+
+    /// get the jmethodID by Method->ConstMethod->ConstantPool->InstanceKlass->_methods_jmethod_ids.
+    pub unsafe fn id(&self) -> Option<jmethodID> {
+        let vs = self.vm_struct;
+        if !vs.has_method_structs() {
+            return None;
+        }
+        let const_method = *(self.at(vs.method_constmethod_offset) as *const *const i8);
+        if !good_ptr(const_method) {
+            return None;
+        }
+        let cpool = *(const_method.offset(vs.constmethod_constants_offset as _) as *const *const i8);
+        let idnum = *(const_method.offset(vs.constmethod_idnum_offset as _) as *const u16) as usize;
+        if !good_ptr(cpool) {
+            return None;
+        }
+        let holder = *(cpool.offset(vs.pool_holder_offset as _) as *const *const i8);
+        if !good_ptr(holder) {
+            return None;
+        }
+        let ids = *(holder.offset(vs.jmethod_ids_offset as _) as *const *const jmethodID);
+        // the first element is the length of the array.
+        if ids.is_null() || idnum >= *ids as usize {
+            return None;
+        }
+        Some(*ids.add(idnum + 1))
+    }
+}
@@ -22,11 +22,25 @@ impl WalkerTrace {
         }
     }
 
+    /// the period of a thread's samples, the ticks signal 8 running threads in turn so the thread
+    /// is sampled every few ticks. it's the median spacing of the sorted sample times, at least
+    /// the interval, the idle gaps of the thread are longer than the median.
+    pub fn sample_period(&self, times: &[u64]) -> u64 {
+        let mut gaps: Vec<u64> = times.windows(2).map(|w| w[1] - w[0]).collect();
+        if gaps.is_empty() {
+            return self.interval;
+        }
+        let mid = gaps.len() / 2;
+        let (_, median, _) = gaps.select_nth_unstable(mid);
+        (*median).max(self.interval)
+    }
+
     pub fn stop(&mut self) {
         self.running.store(false, Ordering::Release);
     }
 
-    pub fn run(&mut self) {
+    /// send the signal to the running threads, the `on_tick` is called after every tick.
+    pub fn run<F: FnMut()>(&mut self, mut on_tick: F) {
         let mut thread_list = OSThreadList::new();
         let self_tid = OS::thread_id();
         self.running.store(true, Ordering::Relaxed);
@@ -50,8 +64,26 @@ impl WalkerTrace {
                     count += 1;
                 }
             }
+            on_tick();
             let duration = Duration::from_nanos(self.interval);
             std::thread::sleep(duration);
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample_period() {
+        let walker = WalkerTrace::new();
+        assert_eq!(walker.sample_period(&[]), MIN_INTERVAL);
+        assert_eq!(walker.sample_period(&[5]), MIN_INTERVAL);
+        // sampled every 3 ticks with an idle gap.
+        let period = MIN_INTERVAL * 3;
+        let times = [0, period, period * 2, period * 10, period * 11];
+        assert_eq!(walker.sample_period(&times), period);
+        assert_eq!(walker.sample_period(&[0, 1, 2]), MIN_INTERVAL);
+    }
+}